@@ -0,0 +1,619 @@
+//! Masters with any number of slaves.  The 8259A lets a slave sit on any
+//! of the master's eight inputs, and some industrial ISA backplanes use
+//! several of them, each with its own ports and offset.  `ChainedPics`
+//! only knows about a single slave, so `CascadedPics` takes a
+//! `CascadeTopology` describing the whole arrangement instead.
+//!
+//! There can be up to 64 interrupt lines in such a system, which won't fit
+//! in an `Irq`, so lines are identified by the vector they're delivered on.
+
+use core::iter;
+use port::{PortIo, X86PortIo};
+use {
+    Delay, Eoi, Icw4, InterruptVector, Pic, PicShadow, VectorLayoutError, CMD_INIT,
+    CMD_INIT_SINGLE, FIRST_FREE_VECTOR,
+};
+
+/// Where a single PIC lives, and where its interrupts are delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PicConfig {
+    /// The I/O port on which we send commands.
+    pub command: u16,
+    /// The I/O port on which we send and receive data.
+    pub data: u16,
+    /// The base offset to which the PIC's interrupts are mapped.
+    pub offset: u8,
+}
+
+/// A master PIC, and the slaves chained to each of its inputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CascadeTopology {
+    master: PicConfig,
+    slaves: [Option<PicConfig>; 8],
+}
+
+impl CascadeTopology {
+    /// A topology with just a master, and nothing chained to it yet.
+    pub const fn new(master: PicConfig) -> CascadeTopology {
+        CascadeTopology {
+            master,
+            slaves: [None; 8],
+        }
+    }
+
+    /// Chain a slave to the specified input of the master, replacing any
+    /// slave which was already there.  Panics if `input` isn't between 0
+    /// and 7.
+    pub const fn with_slave(mut self, input: u8, slave: PicConfig) -> CascadeTopology {
+        assert!(input < 8, "the master only has inputs 0 through 7");
+        self.slaves[input as usize] = Some(slave);
+        self
+    }
+
+    /// The master PIC.
+    pub fn master(&self) -> PicConfig {
+        self.master
+    }
+
+    /// The slave chained to the specified input of the master, if any.
+    pub fn slave(&self, input: u8) -> Option<PicConfig> {
+        self.slaves
+            .get(input as usize)
+            .cloned()
+            .and_then(|slave| slave)
+    }
+
+    /// The master's inputs which have slaves, as a bitmask.  This is what
+    /// the master expects in ICW3.
+    pub fn cascade_inputs(&self) -> u8 {
+        cascade_inputs(&self.slaves)
+    }
+
+    /// Check that every PIC gets its own block of eight vectors, clear of
+    /// the processor's exceptions, just as `ChainedPics::validate_offsets`
+    /// does for a pair.
+    pub const fn validate(&self) -> Result<(), VectorLayoutError> {
+        let mut i = 0;
+        while i < 9 {
+            if let Some(offset) = self.offset(i) {
+                if offset & 0x07 != 0 {
+                    return Err(VectorLayoutError::Misaligned(offset));
+                } else if offset < FIRST_FREE_VECTOR {
+                    return Err(VectorLayoutError::ReservedVector(offset));
+                }
+                let mut j = i + 1;
+                while j < 9 {
+                    if let Some(other) = self.offset(j) {
+                        if offset == other {
+                            return Err(VectorLayoutError::Overlapping);
+                        }
+                    }
+                    j += 1;
+                }
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// The offset of the master if `i` is 0, or of the slave on input
+    /// `i - 1` otherwise.
+    const fn offset(&self, i: usize) -> Option<u8> {
+        if i == 0 {
+            return Some(self.master.offset);
+        }
+        match self.slaves[i - 1] {
+            Some(slave) => Some(slave.offset),
+            None => None,
+        }
+    }
+}
+
+/// One value for the master and one for each of its inputs, such as a
+/// register we've read from every PIC.  Inputs without a slave are `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PerPic<T> {
+    /// The master's value.
+    pub master: T,
+    /// The value for the slave on each of the master's inputs.
+    pub slaves: [Option<T>; 8],
+}
+
+/// A master PIC with any number of slaves, set up according to a
+/// `CascadeTopology`.  All I/O goes through `P`, which defaults to the real
+/// x86 ports.
+pub struct CascadedPics<P = X86PortIo> {
+    master: Pic,
+    slaves: [Option<Pic>; 8],
+    io: P,
+    delay: Delay,
+    shadow: Option<PerPic<PicShadow>>,
+}
+
+impl CascadedPics {
+    /// Create a new interface for the PICs described by `topology`.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn new(topology: CascadeTopology) -> CascadedPics {
+        CascadedPics::with_port_io(topology, X86PortIo)
+    }
+
+    /// Like `new`, but check that the offsets make sense first.  See
+    /// `CascadeTopology::validate`.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn try_new(
+        topology: CascadeTopology,
+    ) -> Result<CascadedPics, VectorLayoutError> {
+        match topology.validate() {
+            Ok(()) => Ok(CascadedPics::new(topology)),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<P: PortIo> CascadedPics<P> {
+    /// Create a new interface for the PICs described by `topology`, using
+    /// the specified backend to reach their I/O ports.
+    pub const unsafe fn with_port_io(topology: CascadeTopology, io: P) -> CascadedPics<P> {
+        const NO_SLAVE: Option<Pic> = None;
+        let mut slaves = [NO_SLAVE; 8];
+        let mut input = 0;
+        while input < 8 {
+            if let Some(slave) = topology.slaves[input] {
+                slaves[input] = Some(Pic::new(slave.offset, slave.command, slave.data));
+            }
+            input += 1;
+        }
+        let master = topology.master;
+        CascadedPics {
+            master: Pic::new(master.offset, master.command, master.data),
+            slaves,
+            io,
+            delay: Delay::Port80,
+            shadow: None,
+        }
+    }
+
+    /// Like `with_port_io`, but check that the offsets make sense first.
+    /// See `CascadeTopology::validate`.
+    pub unsafe fn try_with_port_io(
+        topology: CascadeTopology,
+        io: P,
+    ) -> Result<CascadedPics<P>, VectorLayoutError> {
+        topology.validate()?;
+        Ok(CascadedPics::with_port_io(topology, io))
+    }
+
+    /// Choose how we pause between the writes of our initialization
+    /// sequence.  The default is `Delay::Port80`.
+    pub const fn with_delay(mut self, delay: Delay) -> CascadedPics<P> {
+        self.delay = delay;
+        self
+    }
+
+    /// Choose the mode bytes for the master and for every slave, which take
+    /// effect the next time we're initialized.
+    pub const fn with_icw4(mut self, master: Icw4, slaves: Icw4) -> CascadedPics<P> {
+        self.master.mode = master;
+        let mut input = 0;
+        while input < 8 {
+            if let Some(ref mut slave) = self.slaves[input] {
+                slave.mode = slaves;
+            }
+            input += 1;
+        }
+        self
+    }
+
+    /// Get a reference to our port backend.
+    pub fn port_io(&self) -> &P {
+        &self.io
+    }
+
+    /// Get a mutable reference to our port backend.
+    pub fn port_io_mut(&mut self) -> &mut P {
+        &mut self.io
+    }
+
+    /// Initialize the master and all of its slaves, keeping their current
+    /// masks.  Each slave learns which input it's on, and the master learns
+    /// which of its inputs have slaves.  If there aren't any slaves at all,
+    /// we initialize the master in single mode.
+    pub unsafe fn initialize(&mut self) {
+        let masks = self.read_masks();
+        self.initialize_with_mask(masks)
+    }
+
+    /// Initialize the PICs, as with `initialize`, but load the provided
+    /// masks rather than keeping the current ones.  Slaves whose mask is
+    /// `None` have all their lines masked.
+    pub unsafe fn initialize_with_mask(&mut self, masks: PerPic<u8>) {
+        // Work out everything we're going to send, and keep a copy, since
+        // the PICs can't tell us afterwards.
+        let cascades = cascade_inputs(&self.slaves);
+        let icw1 = if cascades == 0 {
+            CMD_INIT_SINGLE
+        } else {
+            CMD_INIT
+        };
+        let mut shadow = PerPic {
+            master: self.master.begin_initialize(icw1, cascades, masks.master),
+            slaves: [None; 8],
+        };
+        for (input, slave) in self.slaves.iter_mut().enumerate() {
+            if let Some(ref mut slave) = *slave {
+                let mask = masks.slaves[input].unwrap_or(0xFF);
+                shadow.slaves[input] = Some(slave.begin_initialize(CMD_INIT, input as u8, mask));
+            }
+        }
+        self.shadow = Some(shadow);
+
+        let slaves = self
+            .slaves
+            .iter()
+            .zip(shadow.slaves.iter())
+            .filter_map(|(slave, shadow)| Some((slave.as_ref()?, shadow.as_ref()?)));
+        Pic::initialize_chain(
+            &mut self.io,
+            self.delay,
+            iter::once((&self.master, &shadow.master)).chain(slaves),
+        );
+    }
+
+    /// Everything we sent the PICs the last time we initialized them, or
+    /// `None` if we haven't yet.
+    pub fn shadow(&self) -> Option<PerPic<PicShadow>> {
+        self.shadow
+    }
+
+    /// Get the PICs out of the way, as with `ChainedPics::disable`.  We
+    /// mask every line, end any interrupts which are still in service, and
+    /// then move the PICs to the top of the vector space: the master gets
+    /// the lowest block, and each slave the next block of eight, in input
+    /// order, finishing at 0xFF.  With a single slave, that's 0xF0 and
+    /// 0xF8, just like `ChainedPics::disable`.
+    pub unsafe fn disable(&mut self) {
+        self.mask_all();
+        for slave in self.slaves.iter().flatten() {
+            slave.drain(&mut self.io);
+        }
+        self.master.drain(&mut self.io);
+        let slaves = self.slaves.iter().flatten().count() as u8;
+        let mut offset = 0xF8 - 8 * slaves;
+        self.master.offset = offset;
+        for slave in self.slaves.iter_mut().flatten() {
+            offset += 8;
+            slave.offset = offset;
+        }
+        self.initialize_with_mask(PerPic {
+            master: 0xFF,
+            slaves: [Some(0xFF); 8],
+        });
+    }
+
+    /// Read the interrupt request register of every PIC, which shows the
+    /// lines that have been raised but not yet delivered.
+    pub unsafe fn read_irr(&mut self) -> PerPic<u8> {
+        self.read_each(|pic, io| pic.read_irr(io))
+    }
+
+    /// Read the in-service register of every PIC, which shows the lines
+    /// that have been delivered but not yet acknowledged with an end of
+    /// interrupt.
+    pub unsafe fn read_isr(&mut self) -> PerPic<u8> {
+        self.read_each(|pic, io| pic.read_isr(io))
+    }
+
+    /// Read the interrupt mask of every PIC.  A set bit means that the
+    /// corresponding line is masked.
+    pub unsafe fn read_masks(&mut self) -> PerPic<u8> {
+        self.read_each(|pic, io| pic.read_mask(io))
+    }
+
+    /// Do we handle this interrupt?  The master's inputs which have slaves
+    /// never deliver their own vectors, so those don't count.
+    pub fn handles_interrupt<V: Into<InterruptVector>>(&self, interrupt_id: V) -> bool {
+        self.locate(interrupt_id.into().number()).is_some()
+    }
+
+    /// Mask every line on every PIC.
+    pub unsafe fn mask_all(&mut self) {
+        self.master.write_mask(&mut self.io, 0xFF);
+        for slave in self.slaves.iter().flatten() {
+            slave.write_mask(&mut self.io, 0xFF);
+        }
+    }
+
+    /// Mask the line which delivers the specified interrupt.  Vectors which
+    /// don't belong to us are ignored.
+    pub unsafe fn mask_interrupt<V: Into<InterruptVector>>(&mut self, interrupt_id: V) {
+        if let Some((input, line)) = self.locate(interrupt_id.into().number()) {
+            let (pic, io) = self.pic(input);
+            let mask = pic.read_mask(io);
+            pic.write_mask(io, mask | (1 << line));
+        }
+    }
+
+    /// Unmask the line which delivers the specified interrupt.  Unmasking
+    /// a line on a slave also unmasks the master input it's chained to.
+    /// Vectors which don't belong to us are ignored.
+    pub unsafe fn unmask_interrupt<V: Into<InterruptVector>>(&mut self, interrupt_id: V) {
+        if let Some((input, line)) = self.locate(interrupt_id.into().number()) {
+            let (pic, io) = self.pic(input);
+            let mask = pic.read_mask(io);
+            pic.write_mask(io, mask & !(1 << line));
+            if let Some(input) = input {
+                let mask = self.master.read_mask(&mut self.io);
+                self.master.write_mask(&mut self.io, mask & !(1 << input));
+            }
+        }
+    }
+
+    /// Is the line which delivers the specified interrupt currently masked?
+    /// Vectors which don't belong to us always count as masked.
+    pub unsafe fn is_masked<V: Into<InterruptVector>>(&mut self, interrupt_id: V) -> bool {
+        match self.locate(interrupt_id.into().number()) {
+            Some((input, line)) => {
+                let (pic, io) = self.pic(input);
+                pic.read_mask(io) & (1 << line) != 0
+            }
+            None => true,
+        }
+    }
+
+    /// Check whether this interrupt is spurious, and if it is, tell the
+    /// PICs whatever they need to know about it.  A spurious interrupt from
+    /// the master must not be acknowledged at all, but one from a slave
+    /// still went through the master, which needs an end of interrupt on
+    /// that slave's input.
+    ///
+    /// Returns `true` if the interrupt was spurious, in which case the
+    /// handler should return immediately without calling
+    /// `notify_end_of_interrupt`.
+    pub unsafe fn handle_spurious_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) -> bool {
+        let interrupt_id = interrupt_id.into().number();
+        match self.locate(interrupt_id) {
+            Some((None, _)) => self
+                .master
+                .is_spurious_interrupt(&mut self.io, interrupt_id),
+            Some((Some(input), _)) => {
+                let slave = slave(&self.slaves, input);
+                self.master
+                    .handle_spurious_slave_interrupt(&mut self.io, slave, interrupt_id)
+            }
+            None => false,
+        }
+    }
+
+    /// Tell the PICs that we've finished handling this interrupt.  If it
+    /// came from a slave, both the slave and the master need to know,
+    /// although a master in special fully nested mode only hears about it
+    /// once the slave has nothing left in service.
+    pub unsafe fn notify_end_of_interrupt<V: Into<InterruptVector>>(&mut self, interrupt_id: V) {
+        self.internal_notify_end_of_interrupt(interrupt_id.into(), false)
+    }
+
+    /// Like `notify_end_of_interrupt`, but tell each PIC exactly which of
+    /// its lines we've finished with.
+    pub unsafe fn notify_specific_end_of_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) {
+        self.internal_notify_end_of_interrupt(interrupt_id.into(), true)
+    }
+
+    /// Send the right kind of end of interrupt to every PIC involved in
+    /// delivering this interrupt.
+    unsafe fn internal_notify_end_of_interrupt(
+        &mut self,
+        interrupt_id: InterruptVector,
+        specific: bool,
+    ) {
+        match self.locate(interrupt_id.number()) {
+            Some((Some(input), line)) => {
+                let slave = slave(&self.slaves, input);
+                self.master.end_of_cascaded_interrupt(
+                    &mut self.io,
+                    slave,
+                    input,
+                    line,
+                    specific,
+                    false,
+                );
+            }
+            Some((None, line)) => {
+                let eoi = Eoi::new(specific, false, line);
+                self.master.end_of_interrupt(&mut self.io, eoi);
+            }
+            None => {}
+        }
+    }
+
+    /// Ask the PICs for the highest-priority pending interrupt, as with
+    /// `ChainedPics::poll`, and return the vector it would have been
+    /// delivered on.  If the master reports an input with a slave, we go on
+    /// to poll that slave.  The interrupt is now in service, and needs an
+    /// end of interrupt.
+    pub unsafe fn poll(&mut self) -> Option<InterruptVector> {
+        let line = self.master.poll(&mut self.io)?;
+        match self.slaves[line as usize] {
+            Some(ref slave) => {
+                let slave_line = self.master.poll_slave(&mut self.io, slave)?;
+                Some(InterruptVector::new(slave.offset.wrapping_add(slave_line)))
+            }
+            None => Some(InterruptVector::new(self.master.offset.wrapping_add(line))),
+        }
+    }
+
+    /// Read something from every PIC.
+    unsafe fn read_each<F>(&mut self, read: F) -> PerPic<u8>
+    where
+        F: Fn(&Pic, &mut P) -> u8,
+    {
+        let mut values = PerPic {
+            master: read(&self.master, &mut self.io),
+            slaves: [None; 8],
+        };
+        for (value, slave) in values.slaves.iter_mut().zip(self.slaves.iter()) {
+            if let Some(ref slave) = *slave {
+                *value = Some(read(slave, &mut self.io));
+            }
+        }
+        values
+    }
+
+    /// Find the line which delivers the specified vector, as the master
+    /// input of the slave it's on (or `None` for the master itself) and
+    /// the line on that PIC.
+    fn locate(&self, interrupt_id: u8) -> Option<(Option<u8>, u8)> {
+        if self.master.handles_interrupt(interrupt_id) {
+            let line = interrupt_id - self.master.offset;
+            if self.slaves[line as usize].is_none() {
+                return Some((None, line));
+            }
+        }
+        self.slaves
+            .iter()
+            .enumerate()
+            .find_map(|(input, slave)| match *slave {
+                Some(ref slave) if slave.handles_interrupt(interrupt_id) => {
+                    Some((Some(input as u8), interrupt_id - slave.offset))
+                }
+                _ => None,
+            })
+    }
+
+    /// The master if `input` is `None`, or otherwise the slave on that
+    /// input, as returned by `locate`, along with our port backend.
+    fn pic(&mut self, input: Option<u8>) -> (&Pic, &mut P) {
+        let pic = match input {
+            Some(input) => slave(&self.slaves, input),
+            None => &self.master,
+        };
+        (pic, &mut self.io)
+    }
+}
+
+/// The slave on the specified input, as returned by `CascadedPics::locate`.
+fn slave(slaves: &[Option<Pic>; 8], input: u8) -> &Pic {
+    slaves[input as usize]
+        .as_ref()
+        .expect("no slave on this input")
+}
+
+/// The inputs which have slaves, as a bitmask.
+fn cascade_inputs<T>(slaves: &[Option<T>; 8]) -> u8 {
+    slaves
+        .iter()
+        .enumerate()
+        .filter(|&(_, slave)| slave.is_some())
+        .fold(0, |mask, (input, _)| mask | 1 << input)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::vec::Vec;
+    use super::{CascadeTopology, CascadedPics, PicConfig};
+    use port::PortIo;
+    use Delay;
+
+    /// Records every write, and reads as zero, so masks and in-service
+    /// registers are all empty.
+    #[derive(Default)]
+    struct Recorder {
+        writes: Vec<(u16, u8)>,
+    }
+
+    impl Recorder {
+        /// The bytes written to one port, in order.
+        fn writes_to(&self, port: u16) -> Vec<u8> {
+            self.writes
+                .iter()
+                .filter(|&&(p, _)| p == port)
+                .map(|&(_, value)| value)
+                .collect()
+        }
+    }
+
+    impl PortIo for Recorder {
+        unsafe fn read(&mut self, _port: u16) -> u8 {
+            0x00
+        }
+
+        unsafe fn write(&mut self, port: u16, value: u8) {
+            self.writes.push((port, value));
+        }
+    }
+
+    /// A master with slaves on inputs 2 and 5, the second of them at
+    /// nonstandard ports.
+    fn two_slaves() -> CascadedPics<Recorder> {
+        let topology = CascadeTopology::new(PicConfig {
+            command: 0x20,
+            data: 0x21,
+            offset: 0x20,
+        })
+        .with_slave(
+            2,
+            PicConfig {
+                command: 0xA0,
+                data: 0xA1,
+                offset: 0x28,
+            },
+        )
+        .with_slave(
+            5,
+            PicConfig {
+                command: 0x300,
+                data: 0x301,
+                offset: 0x30,
+            },
+        );
+        let mut pics = unsafe { CascadedPics::try_with_port_io(topology, Recorder::default()) }
+            .unwrap()
+            .with_delay(Delay::Immediate);
+        unsafe { pics.initialize() };
+        pics
+    }
+
+    #[test]
+    fn initialize_tells_each_pic_about_the_cascade() {
+        let pics = two_slaves();
+        let io = pics.port_io();
+        assert_eq!(io.writes_to(0x21), [0x20, 0x24, 0x01, 0x00]);
+        assert_eq!(io.writes_to(0xA1), [0x28, 0x02, 0x01, 0x00]);
+        assert_eq!(io.writes_to(0x301), [0x30, 0x05, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn end_of_interrupt_on_the_second_slave() {
+        let mut pics = two_slaves();
+        pics.port_io_mut().writes.clear();
+        unsafe { pics.notify_end_of_interrupt(0x33) };
+        assert_eq!(pics.port_io().writes, [(0x300, 0x20), (0x20, 0x20)]);
+    }
+
+    #[test]
+    fn spurious_slave_interrupt_ends_only_the_master() {
+        let mut pics = two_slaves();
+        pics.port_io_mut().writes.clear();
+        assert!(unsafe { pics.handle_spurious_interrupt(0x37) });
+        let io = pics.port_io();
+        assert_eq!(io.writes_to(0x20), [0x20]);
+        assert!(!io.writes_to(0x300).contains(&0x20));
+    }
+
+    #[test]
+    fn disable_packs_the_pics_at_the_top_of_the_vector_space() {
+        let mut pics = two_slaves();
+        unsafe { pics.disable() };
+        let shadow = pics.shadow().unwrap();
+        assert_eq!(shadow.master.icw2, 0xE8);
+        assert_eq!(shadow.slaves[2].unwrap().icw2, 0xF0);
+        assert_eq!(shadow.slaves[5].unwrap().icw2, 0xF8);
+    }
+}
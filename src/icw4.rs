@@ -0,0 +1,140 @@
+//! The mode byte, ICW4, which ends each PIC's initialization sequence.
+//! Almost everyone wants the default, which is 8086 mode with ordinary
+//! EOIs, but some boards need automatic EOIs, buffered mode or special
+//! fully nested mode, so `Icw4` lets you build whichever byte you need.
+
+/// ICW4 bit: we're attached to an 8086 or later, rather than an 8080 or
+/// 8085.
+const MODE_8086: u8 = 0x01;
+
+/// ICW4 bit: end interrupts automatically.
+const MODE_AUTO_EOI: u8 = 0x02;
+
+/// ICW4 bit: in buffered mode, this PIC is the master.
+const MODE_BUFFERED_MASTER: u8 = 0x04;
+
+/// ICW4 bit: use buffered mode.
+const MODE_BUFFERED: u8 = 0x08;
+
+/// ICW4 bit: use special fully nested mode.
+const MODE_SPECIAL_FULLY_NESTED: u8 = 0x10;
+
+/// Whether a PIC drives a bus transceiver, and if so, which role it has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferedMode {
+    /// The PIC's SP/EN pin says whether it's the master or a slave.  This
+    /// is what PCs do.
+    Unbuffered,
+    /// The PIC's SP/EN pin enables a data bus transceiver, so it has to be
+    /// told that it's the master.
+    Master,
+    /// The PIC's SP/EN pin enables a data bus transceiver, so it has to be
+    /// told that it's a slave.
+    Slave,
+}
+
+/// The mode byte sent to a PIC at the end of initialization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Icw4(u8);
+
+impl Icw4 {
+    /// 8086 mode, with ordinary EOIs, no buffering and normal nesting.
+    /// This is what PCs use.
+    pub const fn new() -> Icw4 {
+        Icw4(MODE_8086)
+    }
+
+    /// The mode byte for PIC1 on the NEC PC-98 (0x1D).  The PICs sit behind
+    /// bus transceivers, so this is buffered mode as the master, and PIC1
+    /// also runs in special fully nested mode.  See `PortLayout::PC98`.
+    pub const PC98_MASTER: Icw4 =
+        Icw4(MODE_8086 | MODE_BUFFERED | MODE_BUFFERED_MASTER | MODE_SPECIAL_FULLY_NESTED);
+
+    /// The mode byte for PIC2 on the NEC PC-98 (0x09): buffered mode as a
+    /// slave.
+    pub const PC98_SLAVE: Icw4 = Icw4(MODE_8086 | MODE_BUFFERED);
+
+    /// Wrap a raw ICW4 byte.  The top three bits aren't part of ICW4, so we
+    /// clear them.
+    pub const fn from_bits(bits: u8) -> Icw4 {
+        Icw4(bits & 0x1F)
+    }
+
+    /// The raw ICW4 byte.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Choose between 8086 mode and MCS-80/85 mode.  MCS-80/85 mode is only
+    /// for 8080 and 8085 processors, which expect a `CALL` instruction
+    /// rather than a vector, so you almost certainly want 8086 mode.
+    pub const fn with_8086_mode(self, enabled: bool) -> Icw4 {
+        Icw4(set_mode_bit(self.0, MODE_8086, enabled))
+    }
+
+    /// Turn automatic EOI mode on or off.  See
+    /// `ChainedPics::with_auto_eoi`.
+    pub const fn with_auto_eoi(self, enabled: bool) -> Icw4 {
+        Icw4(set_mode_bit(self.0, MODE_AUTO_EOI, enabled))
+    }
+
+    /// Choose whether the PIC drives a bus transceiver, and if so, whether
+    /// it's the master or a slave.
+    pub const fn with_buffered_mode(self, mode: BufferedMode) -> Icw4 {
+        let bits = self.0 & !(MODE_BUFFERED | MODE_BUFFERED_MASTER);
+        match mode {
+            BufferedMode::Unbuffered => Icw4(bits),
+            BufferedMode::Master => Icw4(bits | MODE_BUFFERED | MODE_BUFFERED_MASTER),
+            BufferedMode::Slave => Icw4(bits | MODE_BUFFERED),
+        }
+    }
+
+    /// Turn special fully nested mode on or off.  This only makes sense on
+    /// a master, where it lets a higher-priority interrupt from a slave
+    /// through while a lower-priority one from the same slave is still in
+    /// service.
+    pub const fn with_special_fully_nested(self, enabled: bool) -> Icw4 {
+        Icw4(set_mode_bit(self.0, MODE_SPECIAL_FULLY_NESTED, enabled))
+    }
+
+    /// Are we in 8086 mode, rather than MCS-80/85 mode?
+    pub const fn is_8086_mode(self) -> bool {
+        self.0 & MODE_8086 != 0
+    }
+
+    /// Are we in automatic EOI mode?
+    pub const fn auto_eoi(self) -> bool {
+        self.0 & MODE_AUTO_EOI != 0
+    }
+
+    /// Do we drive a bus transceiver, and in which role?
+    pub const fn buffered_mode(self) -> BufferedMode {
+        if self.0 & MODE_BUFFERED == 0 {
+            BufferedMode::Unbuffered
+        } else if self.0 & MODE_BUFFERED_MASTER != 0 {
+            BufferedMode::Master
+        } else {
+            BufferedMode::Slave
+        }
+    }
+
+    /// Are we in special fully nested mode?
+    pub const fn special_fully_nested(self) -> bool {
+        self.0 & MODE_SPECIAL_FULLY_NESTED != 0
+    }
+}
+
+impl Default for Icw4 {
+    fn default() -> Icw4 {
+        Icw4::new()
+    }
+}
+
+/// Set or clear `bit` in a mode byte.
+const fn set_mode_bit(mode: u8, bit: u8, enabled: bool) -> u8 {
+    if enabled {
+        mode | bit
+    } else {
+        mode & !bit
+    }
+}
@@ -0,0 +1,106 @@
+//! Types which keep IRQ line numbers and interrupt vectors apart.  The PICs
+//! deliver IRQ `n` as interrupt vector `offset + n`, and mixing the two up
+//! is an easy mistake to make when both are plain `u8`s.
+
+/// One of the 16 IRQ lines handled by a pair of chained PICs.  Lines 0
+/// through 7 belong to the master PIC, and lines 8 through 15 to the slave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Irq(u8);
+
+impl Irq {
+    /// The programmable interval timer.
+    pub const TIMER: Irq = Irq(0);
+
+    /// The PS/2 keyboard.
+    pub const KEYBOARD: Irq = Irq(1);
+
+    /// The line on the master PIC to which the slave is chained on a
+    /// PC/AT, where it never fires on its own.  Other layouts, such as the
+    /// PC-98's, chain the slave elsewhere and use this as an ordinary
+    /// line; see `PortLayout::cascade`.
+    pub const CASCADE: Irq = Irq(2);
+
+    /// The second serial port.
+    pub const COM2: Irq = Irq(3);
+
+    /// The first serial port.
+    pub const COM1: Irq = Irq(4);
+
+    /// The first parallel port.
+    pub const LPT1: Irq = Irq(7);
+
+    /// The real-time clock.
+    pub const RTC: Irq = Irq(8);
+
+    /// The PS/2 mouse.
+    pub const MOUSE: Irq = Irq(12);
+
+    /// The floating-point unit.
+    pub const FPU: Irq = Irq(13);
+
+    /// The primary ATA channel.
+    pub const ATA_PRIMARY: Irq = Irq(14);
+
+    /// The secondary ATA channel.
+    pub const ATA_SECONDARY: Irq = Irq(15);
+
+    /// Look up an IRQ line by number, returning `None` unless it is between
+    /// 0 and 15.
+    pub const fn new(irq: u8) -> Option<Irq> {
+        if irq < 16 {
+            Some(Irq(irq))
+        } else {
+            None
+        }
+    }
+
+    /// The number of this IRQ line.
+    pub const fn number(self) -> u8 {
+        self.0
+    }
+
+    /// The index of the PIC which handles this line: 0 for the master and
+    /// 1 for the slave.
+    pub(crate) const fn pic_index(self) -> usize {
+        (self.0 / 8) as usize
+    }
+
+    /// This line's input number on the PIC which handles it.
+    pub(crate) const fn line(self) -> u8 {
+        self.0 % 8
+    }
+}
+
+impl From<Irq> for u8 {
+    fn from(irq: Irq) -> u8 {
+        irq.0
+    }
+}
+
+/// An interrupt vector, as seen by the processor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InterruptVector(u8);
+
+impl InterruptVector {
+    /// Wrap a raw interrupt vector number.
+    pub const fn new(vector: u8) -> InterruptVector {
+        InterruptVector(vector)
+    }
+
+    /// The number of this interrupt vector.
+    pub const fn number(self) -> u8 {
+        self.0
+    }
+}
+
+impl From<u8> for InterruptVector {
+    fn from(vector: u8) -> InterruptVector {
+        InterruptVector(vector)
+    }
+}
+
+impl From<InterruptVector> for u8 {
+    fn from(vector: InterruptVector) -> u8 {
+        vector.0
+    }
+}
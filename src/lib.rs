@@ -18,150 +18,2049 @@
 //! we wanted to write a DOS emulator, we'd presumably need to choose
 //! different base interrupts, because DOS used interrupt 0x21 for system
 //! calls.
+//!
+//! # Safety
+//!
+//! Nearly everything here is `unsafe`, for the same reason: it's really easy
+//! to trigger undefined behavior by misconfiguring the PICs or using them
+//! incorrectly, for example by mapping their interrupts on top of the
+//! processor's exceptions, or by sending an end of interrupt for an
+//! interrupt which isn't in service.  Rather than repeat that on every
+//! function, we say it once here.
 
 #![warn(missing_docs)]
-#![feature(const_fn)]
+#![allow(clippy::missing_safety_doc)]
 #![no_std]
 
-extern crate x86_64;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+mod cascade;
+mod icw4;
+mod irq;
+mod port;
+
+#[cfg(feature = "model")]
+pub mod model;
 
-use x86_64::instructions::port::Port;
+pub use cascade::{CascadeTopology, CascadedPics, PerPic, PicConfig};
+pub use icw4::{BufferedMode, Icw4};
+pub use irq::{InterruptVector, Irq};
+pub use port::{PortIo, X86PortIo};
 
 /// The interrupt ID for the timer interrupt.
-pub const TIMER_INTERRUPT_ID: u8 = PIC_1_OFFSET;
+pub const TIMER_INTERRUPT_ID: u8 = DefaultChainedPics::vector_for_irq(Irq::TIMER).number();
 
-const PIC_1_OFFSET: u8 = 0x20;
-const PIC_2_OFFSET: u8 = 0x28;
+/// The PICs used by the free functions in this module, which map their
+/// interrupts to 0x20 through 0x2F.
+pub type DefaultChainedPics = FixedChainedPics<0x20, 0x28>;
 
 /// Initialize both our PICs.  We initialize them together, at the same
 /// time, because it's traditional to do so, and because I/O operations
 /// might not be instantaneous on older processors.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub unsafe fn initialize() {
-    internal_initialize_with_mask(None, None)
+    create_pic_structs().initialize()
 }
 
 /// Initialize both our PICs.  We initialize them together, at the same
 /// time, because it's traditional to do so, and because I/O operations
 /// might not be instantaneous on older processors.
 /// The PICs are initialized with the provided masks rather than defaults.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub unsafe fn initialize_with_mask(pic1_mask: u8, pic2_mask: u8) {
-    internal_initialize_with_mask(Some(pic1_mask), Some(pic2_mask));
-}
-
-unsafe fn internal_initialize_with_mask(pic1_mask: Option<u8>, pic2_mask: Option<u8>) {
-    let (mut pic_1, mut pic_2) = create_pic_structs();
-
-    // We need to add a delay between writes to our PICs, especially on
-    // older motherboards.  But we don't necessarily have any kind of
-    // timers yet, because most of them require interrupts.  Various
-    // older versions of Linux and other PC operating systems have
-    // worked around this by writing garbage data to port 0x80, which
-    // allegedly takes long enough to make everything work on most
-    // hardware.  Here, `wait` is a closure.
-    let mut wait_port: Port<u8> = Port::new(0x80);
-    let mut wait = || wait_port.write(0);
-
-    // Save our original interrupt masks, because I'm too lazy to
-    // figure out reasonable values.  We'll restore these when we're
-    // done.
-    let saved_mask1 = pic_1.data.read();
-    let saved_mask2 = pic_2.data.read();
-
-    // Tell each PIC that we're going to send it a three-byte
-    // initialization sequence on its data port.
-    pic_1.command.write(CMD_INIT);
-    wait();
-    pic_2.command.write(CMD_INIT);
-    wait();
-
-    // Byte 1: Set up our base offsets.
-    pic_1.data.write(pic_1.offset);
-    wait();
-    pic_2.data.write(pic_2.offset);
-    wait();
-
-    // Byte 2: Configure chaining between PIC1 and PIC2.
-    pic_1.data.write(4);
-    wait();
-    pic_2.data.write(2);
-    wait();
-
-    // Byte 3: Set our mode.
-    pic_1.data.write(MODE_8086);
-    wait();
-    pic_2.data.write(MODE_8086);
-    wait();
-
-    // Restore our saved masks.
-    pic_1.data.write(pic1_mask.unwrap_or(saved_mask1));
-    pic_2.data.write(pic2_mask.unwrap_or(saved_mask2));
+    create_pic_structs().initialize_with_mask(pic1_mask, pic2_mask);
 }
 
 /// Do we handle this interrupt?
-pub fn handles_interrupt(interrupt_id: u8) -> bool {
-    let (pic_1, pic_2) = create_pic_structs();
-    pic_1.handles_interrupt(interrupt_id) || pic_2.handles_interrupt(interrupt_id)
+pub fn handles_interrupt<V: Into<InterruptVector>>(interrupt_id: V) -> bool {
+    DefaultChainedPics::handles_interrupt(interrupt_id.into())
 }
 
 /// Figure out which PIC needs to know about this
 /// interrupt.  This is tricky, because all interrupts from pic 2
 /// get chained through pic 1.
-pub unsafe fn notify_end_of_interrupt(interrupt_id: u8) {
-    let (mut pic_1, mut pic_2) = create_pic_structs();
-    if pic_1.handles_interrupt(interrupt_id) || pic_2.handles_interrupt(interrupt_id) {
-        if pic_2.handles_interrupt(interrupt_id) {
-            pic_2.end_of_interrupt();
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub unsafe fn notify_end_of_interrupt<V: Into<InterruptVector>>(interrupt_id: V) {
+    create_pic_structs().notify_end_of_interrupt(interrupt_id)
+}
+
+/// Find out whether the standard PICs are present.  See
+/// `ChainedPics::probe`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub unsafe fn probe() -> PicPresence {
+    create_pic_structs().probe()
+}
+
+/// Build the PIC pair used by the free functions above, which always
+/// live at our default offsets.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn create_pic_structs() -> DefaultChainedPics {
+    unsafe { DefaultChainedPics::new() }
+}
+
+/// Which interrupt controller legacy interrupts are routed to, according to
+/// the IMCR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImcrMode {
+    /// Interrupts go straight to the PICs, bypassing the APIC.  This is how
+    /// the machine boots.
+    Pic,
+    /// Interrupts go through the APIC, in "symmetric I/O" mode.
+    Apic,
+}
+
+/// The Interrupt Mode Configuration Register, on ports 0x22 and 0x23.
+/// Older machines which follow the MultiProcessor Specification use this
+/// to decide whether the PICs are wired straight to the processor or go
+/// through the APIC, so it needs to be switched to `ImcrMode::Apic` before
+/// the PICs are disabled.  Machines without an IMCR ignore it.
+pub struct Imcr<P = X86PortIo> {
+    io: P,
+}
+
+impl Imcr {
+    /// Create a new interface to the IMCR.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn new() -> Imcr {
+        Imcr::with_port_io(X86PortIo)
+    }
+}
+
+impl<P: PortIo> Imcr<P> {
+    /// Create a new interface to the IMCR, using the specified backend to
+    /// reach its I/O ports.
+    pub const unsafe fn with_port_io(io: P) -> Imcr<P> {
+        Imcr { io }
+    }
+
+    /// Find out where interrupts are currently routed.
+    pub unsafe fn mode(&mut self) -> ImcrMode {
+        self.io.write(IMCR_SELECT_PORT, IMCR_REGISTER);
+        if self.io.read(IMCR_DATA_PORT) & IMCR_APIC_MODE != 0 {
+            ImcrMode::Apic
+        } else {
+            ImcrMode::Pic
+        }
+    }
+
+    /// Route interrupts to the PICs or to the APIC.
+    pub unsafe fn set_mode(&mut self, mode: ImcrMode) {
+        self.io.write(IMCR_SELECT_PORT, IMCR_REGISTER);
+        let value = self.io.read(IMCR_DATA_PORT);
+        let value = match mode {
+            ImcrMode::Pic => value & !IMCR_APIC_MODE,
+            ImcrMode::Apic => value | IMCR_APIC_MODE,
+        };
+        self.io.write(IMCR_SELECT_PORT, IMCR_REGISTER);
+        self.io.write(IMCR_DATA_PORT, value);
+    }
+}
+
+/// How an IRQ line signals an interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// A rising edge raises an interrupt.  This is the ISA default.
+    Edge,
+    /// Holding the line asserted raises an interrupt.  PCI devices, which
+    /// may share lines, need this.
+    Level,
+}
+
+/// Errors returned when configuring the ELCR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElcrError {
+    /// This IRQ is wired to a motherboard device which only works with
+    /// edge triggering, so it can't be made level-triggered.
+    EdgeOnly(Irq),
+}
+
+impl fmt::Display for ElcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ElcrError::EdgeOnly(irq) => {
+                write!(f, "IRQ {} must stay edge-triggered", irq.number())
+            }
+        }
+    }
+}
+
+/// The EISA Edge/Level Control Registers, on ports 0x4D0 and 0x4D1, which
+/// pick the trigger mode of each IRQ line on chipsets that support them.
+/// We refuse to make IRQs 0, 1, 2, 8 and 13 level-triggered, because the
+/// timer, keyboard, cascade, RTC and FPU are all edge-triggered.
+pub struct Elcr<P = X86PortIo> {
+    io: P,
+}
+
+impl Elcr {
+    /// Create a new interface to the ELCR.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn new() -> Elcr {
+        Elcr::with_port_io(X86PortIo)
+    }
+}
+
+impl<P: PortIo> Elcr<P> {
+    /// Create a new interface to the ELCR, using the specified backend to
+    /// reach its I/O ports.
+    pub const unsafe fn with_port_io(io: P) -> Elcr<P> {
+        Elcr { io }
+    }
+
+    /// Read both registers.  Bit `n` of the result is set if IRQ `n` is
+    /// level-triggered.
+    pub unsafe fn read(&mut self) -> u16 {
+        let elcr1 = self.io.read(ELCR_PORTS[0]);
+        let elcr2 = self.io.read(ELCR_PORTS[1]);
+        (elcr2 as u16) << 8 | elcr1 as u16
+    }
+
+    /// Write both registers, in the same format that `read` returns.  If
+    /// this would make any edge-only IRQ level-triggered, we write nothing
+    /// and return an error.
+    pub unsafe fn write(&mut self, levels: u16) -> Result<(), ElcrError> {
+        let forbidden = levels & ELCR_EDGE_ONLY;
+        if forbidden != 0 {
+            let irq = Irq::new(forbidden.trailing_zeros() as u8).unwrap();
+            return Err(ElcrError::EdgeOnly(irq));
+        }
+        self.write_unchecked(levels);
+        Ok(())
+    }
+
+    /// Write both registers, without checking for edge-only IRQs.
+    unsafe fn write_unchecked(&mut self, levels: u16) {
+        self.io.write(ELCR_PORTS[0], levels as u8);
+        self.io.write(ELCR_PORTS[1], (levels >> 8) as u8);
+    }
+
+    /// Find out how the specified IRQ line is triggered.
+    pub unsafe fn trigger_mode(&mut self, irq: Irq) -> TriggerMode {
+        if self.read() & (1 << irq.number()) != 0 {
+            TriggerMode::Level
+        } else {
+            TriggerMode::Edge
+        }
+    }
+
+    /// Change how the specified IRQ line is triggered, leaving the others
+    /// alone.  Only making an edge-only IRQ level-triggered is an error, so
+    /// if the firmware left one of them level-triggered, we don't mind, and
+    /// you can use this to put it right.
+    pub unsafe fn set_trigger_mode(
+        &mut self,
+        irq: Irq,
+        mode: TriggerMode,
+    ) -> Result<(), ElcrError> {
+        let levels = self.read();
+        let bit = 1 << irq.number();
+        match mode {
+            TriggerMode::Edge => self.write_unchecked(levels & !bit),
+            TriggerMode::Level if ELCR_EDGE_ONLY & bit != 0 => {
+                return Err(ElcrError::EdgeOnly(irq));
+            }
+            TriggerMode::Level => self.write_unchecked(levels | bit),
+        }
+        Ok(())
+    }
+}
+
+/// How to pause between the writes of an initialization sequence.
+///
+/// We need to add a delay between writes to our PICs, especially on older
+/// motherboards.  But we don't necessarily have any kind of timers yet,
+/// because most of them require interrupts.  Various older versions of
+/// Linux and other PC operating systems have worked around this by writing
+/// garbage data to port 0x80, which allegedly takes long enough to make
+/// everything work on most hardware.  That isn't always welcome, though:
+/// some boards show port 0x80 on a POST code display, and on some virtual
+/// machines every port write is an expensive exit to the hypervisor.
+#[derive(Clone, Copy)]
+pub enum Delay {
+    /// Write to port 0x80.  This is the default.
+    Port80,
+    /// Write to some other unused I/O port.
+    Port(u16),
+    /// Call something which busy-waits for long enough.  This may be a
+    /// closure which captures calibration data, such as the TSC frequency,
+    /// as long as it lives forever: put it in a `static`, or leak it from a
+    /// `Box` once you've calibrated.
+    BusyWait(&'static (dyn Fn() + Sync)),
+    /// Don't wait at all.  This is only safe on emulators and hypervisors
+    /// known to handle back-to-back writes.
+    Immediate,
+}
+
+impl Delay {
+    /// Pause, using `io` if we need to write to a port.
+    unsafe fn wait<P: PortIo>(self, io: &mut P) {
+        match self {
+            Delay::Port80 => io.write(WAIT_PORT, 0),
+            Delay::Port(port) => io.write(port, 0),
+            Delay::BusyWait(wait) => wait(),
+            Delay::Immediate => {}
+        }
+    }
+}
+
+impl fmt::Debug for Delay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Delay::Port80 => write!(f, "Port80"),
+            Delay::Port(port) => f.debug_tuple("Port").field(&port).finish(),
+            Delay::BusyWait(_) => write!(f, "BusyWait(..)"),
+            Delay::Immediate => write!(f, "Immediate"),
         }
-        pic_1.end_of_interrupt();
     }
 }
 
-fn create_pic_structs() -> (Pic, Pic) {
-    let pic_1 = Pic {
-        offset: PIC_1_OFFSET,
-        command: Port::new(0x20),
-        data: Port::new(0x21),
+/// Where a pair of chained PICs live in I/O space, and which of PIC1's
+/// inputs PIC2 is chained to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortLayout {
+    /// The port on which we send commands to PIC1.
+    pub pic1_command: u16,
+    /// The port on which we send and receive data for PIC1.
+    pub pic1_data: u16,
+    /// The port on which we send commands to PIC2.
+    pub pic2_command: u16,
+    /// The port on which we send and receive data for PIC2.
+    pub pic2_data: u16,
+    /// The line on PIC1 which PIC2 is chained to.
+    pub cascade: u8,
+}
+
+impl PortLayout {
+    /// The IBM PC/AT layout used by every x86 PC: PIC1 on ports 0x20 and
+    /// 0x21, and PIC2 on ports 0xA0 and 0xA1, chained to IRQ 2.
+    pub const PC_AT: PortLayout = PortLayout {
+        pic1_command: 0x20,
+        pic1_data: 0x21,
+        pic2_command: 0xA0,
+        pic2_data: 0xA1,
+        cascade: 2,
     };
-    let pic_2 = Pic {
-        offset: PIC_2_OFFSET,
-        command: Port::new(0xA0),
-        data: Port::new(0xA1),
+
+    /// The NEC PC-98 layout: PIC1 on ports 0x00 and 0x02, and PIC2 on
+    /// ports 0x08 and 0x0A, chained to IRQ 7.  The PICs sit behind bus
+    /// transceivers, so they also need the mode bytes `Icw4::PC98_MASTER`
+    /// and `Icw4::PC98_SLAVE`, which you pass to `with_icw4`.
+    pub const PC98: PortLayout = PortLayout {
+        pic1_command: 0x00,
+        pic1_data: 0x02,
+        pic2_command: 0x08,
+        pic2_data: 0x0A,
+        cascade: 7,
+    };
+}
+
+/// The ways in which a pair of PIC offsets can be wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorLayoutError {
+    /// Both PICs would deliver interrupts on the same vectors.
+    Overlapping,
+    /// This offset would put a PIC's interrupts on top of the vectors
+    /// reserved for processor exceptions, 0x00 through 0x1F.
+    ReservedVector(u8),
+    /// This offset isn't a multiple of 8.
+    Misaligned(u8),
+}
+
+impl fmt::Display for VectorLayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VectorLayoutError::Overlapping => write!(f, "PIC vector ranges overlap"),
+            VectorLayoutError::ReservedVector(offset) => write!(
+                f,
+                "PIC offset {:#x} overlaps the processor's exception vectors",
+                offset
+            ),
+            VectorLayoutError::Misaligned(offset) => {
+                write!(f, "PIC offset {:#x} is not a multiple of 8", offset)
+            }
+        }
+    }
+}
+
+/// A snapshot of a pair of PICs, taken by `ChainedPics::save_state` and
+/// replayed by `ChainedPics::restore_state`.  Each array has PIC1's value
+/// first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PicState {
+    /// The base offset of each PIC.
+    pub offsets: [u8; 2],
+    /// The mode byte (ICW4) sent to each PIC during initialization.
+    pub modes: [Icw4; 2],
+    /// The interrupt mask of each PIC.
+    pub masks: [u8; 2],
+    /// Were the PICs in special mask mode?
+    pub special_mask_mode: bool,
+    /// Were the PICs rotating priorities on automatic EOIs?
+    pub rotate_on_auto_eoi: bool,
+    /// The ELCR contents, if they were saved.
+    pub elcr: Option<u16>,
+}
+
+/// Which legacy PICs a machine has, as reported by `ChainedPics::probe`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PicPresence {
+    /// There are no PICs at all, as on some minimal virtual machines.  Use
+    /// the APIC instead.
+    Absent,
+    /// There's a master PIC, but no slave.
+    MasterOnly,
+    /// There's the usual pair of chained PICs.
+    Cascaded,
+}
+
+/// A copy of everything we sent to one PIC during initialization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PicShadow {
+    /// The initialization command.
+    pub icw1: u8,
+    /// The base offset.
+    pub icw2: u8,
+    /// The cascade configuration.
+    pub icw3: u8,
+    /// The mode.
+    pub icw4: u8,
+    /// The interrupt mask we loaded afterwards.
+    pub mask: u8,
+}
+
+/// The results of `ChainedPics::self_test`.  Each array has PIC1's value
+/// first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// Everything we sent during initialization, or `None` if we never
+    /// initialized the PICs.
+    pub shadow: Option<[PicShadow; 2]>,
+    /// Did each PIC read back the test masks we wrote to it?
+    pub mask_readback: [bool; 2],
+    /// The combined interrupt request registers, as from `read_irr`.
+    pub irr: u16,
+    /// Did the interrupt request registers look like real chips?  A PIC
+    /// which isn't there at all typically reads as 0xFF, with every line
+    /// apparently requesting service.
+    pub irr_sane: bool,
+    /// The combined in-service registers, as from `read_isr`.
+    pub isr: u16,
+    /// Were the in-service registers empty, as they should be outside of
+    /// an interrupt handler?
+    pub isr_sane: bool,
+}
+
+impl SelfTestReport {
+    /// Did everything check out?
+    pub fn passed(&self) -> bool {
+        self.shadow.is_some()
+            && self.mask_readback == [true, true]
+            && self.irr_sane
+            && self.isr_sane
+    }
+
+    /// Did we initialize a single PIC, with no PIC2 to report on?
+    fn single(&self) -> bool {
+        match self.shadow {
+            Some(shadow) => shadow[0].icw1 == CMD_INIT_SINGLE,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = if self.passed() { "passed" } else { "FAILED" };
+        writeln!(f, "8259 PIC self-test {}", verdict)?;
+        let pics = if self.single() { 1 } else { 2 };
+        match self.shadow {
+            Some(shadow) => {
+                for (i, pic) in shadow[..pics].iter().enumerate() {
+                    writeln!(
+                        f,
+                        "  PIC{}: ICW1 {:#04x} ICW2 {:#04x} ICW3 {:#04x} ICW4 {:#04x} mask {:#04x}",
+                        i + 1,
+                        pic.icw1,
+                        pic.icw2,
+                        pic.icw3,
+                        pic.icw4,
+                        pic.mask
+                    )?;
+                }
+            }
+            None => writeln!(f, "  not initialized")?,
+        }
+        for (i, ok) in self.mask_readback[..pics].iter().enumerate() {
+            let result = if *ok { "ok" } else { "mismatch" };
+            writeln!(f, "  PIC{} mask readback: {}", i + 1, result)?;
+        }
+        if pics == 1 {
+            write!(f, "  IRR {:#04x} ISR {:#04x}", self.irr, self.isr)
+        } else {
+            write!(f, "  IRR {:#06x} ISR {:#06x}", self.irr, self.isr)
+        }
+    }
+}
+
+/// A pair of chained PIC controllers.  This is the standard setup on x86.
+/// All I/O goes through `P`, which defaults to the real x86 ports.
+///
+/// Older machines, such as the original PC and XT, have a single PIC with
+/// nothing chained to it.  Use `ChainedPics::new_single` for those; we then
+/// only ever talk to PIC1, and IRQs 8 through 15 don't exist.
+pub struct ChainedPics<P = X86PortIo> {
+    pics: [Pic; 2],
+    io: P,
+    delay: Delay,
+    shadow: Option<[PicShadow; 2]>,
+    /// The line on PIC1 that PIC2 is chained to, or `None` if PIC1 is on
+    /// its own.
+    cascade: Option<u8>,
+}
+
+impl ChainedPics {
+    /// Create a new interface for the standard PIC1 and PIC2 controllers,
+    /// specifying the desired interrupt offsets.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn new(offset1: u8, offset2: u8) -> ChainedPics {
+        ChainedPics::with_port_io(offset1, offset2, X86PortIo)
+    }
+
+    /// Create a new interface for a lone PIC1, with nothing chained to it,
+    /// specifying the desired interrupt offset.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn new_single(offset: u8) -> ChainedPics {
+        ChainedPics::single_with_port_io(offset, X86PortIo)
+    }
+
+    /// Like `new`, but check that the offsets make sense first.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn try_new(
+        offset1: u8,
+        offset2: u8,
+    ) -> Result<ChainedPics, VectorLayoutError> {
+        match ChainedPics::validate_offsets(offset1, offset2) {
+            Ok(()) => Ok(ChainedPics::new(offset1, offset2)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Like `new_single`, but check that the offset makes sense first.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn try_new_single(offset: u8) -> Result<ChainedPics, VectorLayoutError> {
+        match ChainedPics::validate_single_offset(offset) {
+            Ok(()) => Ok(ChainedPics::new_single(offset)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Check that a pair of offsets gives each PIC its own block of eight
+    /// vectors, clear of the processor's exceptions.  The PICs ignore the
+    /// low three bits of their offsets, so an offset which isn't a multiple
+    /// of eight wouldn't do what you expect.
+    pub const fn validate_offsets(offset1: u8, offset2: u8) -> Result<(), VectorLayoutError> {
+        if offset1 & 0x07 != 0 {
+            Err(VectorLayoutError::Misaligned(offset1))
+        } else if offset2 & 0x07 != 0 {
+            Err(VectorLayoutError::Misaligned(offset2))
+        } else if offset1 < FIRST_FREE_VECTOR {
+            Err(VectorLayoutError::ReservedVector(offset1))
+        } else if offset2 < FIRST_FREE_VECTOR {
+            Err(VectorLayoutError::ReservedVector(offset2))
+        } else if offset1 == offset2 {
+            Err(VectorLayoutError::Overlapping)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check the offset of a lone PIC, as `validate_offsets` does for a
+    /// pair: it must be a multiple of eight, clear of the processor's
+    /// exceptions.
+    pub const fn validate_single_offset(offset: u8) -> Result<(), VectorLayoutError> {
+        if offset & 0x07 != 0 {
+            Err(VectorLayoutError::Misaligned(offset))
+        } else if offset < FIRST_FREE_VECTOR {
+            Err(VectorLayoutError::ReservedVector(offset))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<P: PortIo> ChainedPics<P> {
+    /// Create a new interface for the standard PIC1 and PIC2 controllers,
+    /// specifying the desired interrupt offsets and the backend used to
+    /// reach their I/O ports.
+    pub const unsafe fn with_port_io(offset1: u8, offset2: u8, io: P) -> ChainedPics<P> {
+        let layout = PortLayout::PC_AT;
+        ChainedPics {
+            pics: [
+                Pic::new(offset1, layout.pic1_command, layout.pic1_data),
+                Pic::new(offset2, layout.pic2_command, layout.pic2_data),
+            ],
+            io,
+            delay: Delay::Port80,
+            shadow: None,
+            cascade: Some(layout.cascade),
+        }
+    }
+
+    /// Create a new interface for a lone PIC1, specifying the desired
+    /// interrupt offset and the backend used to reach its I/O ports.  We
+    /// initialize it in single mode, and never touch PIC2's ports, which
+    /// may well belong to something else on such a machine.
+    pub const unsafe fn single_with_port_io(offset: u8, io: P) -> ChainedPics<P> {
+        let mut pics = ChainedPics::with_port_io(offset, offset.wrapping_add(8), io);
+        pics.cascade = None;
+        pics
+    }
+
+    /// Choose where our PICs live in I/O space, and which line PIC2 is
+    /// chained to.  The default is `PortLayout::PC_AT`.  In single mode,
+    /// only PIC1's ports are used.  Panics if `layout.cascade` isn't
+    /// between 0 and 7.  This doesn't touch the mode bytes, so boards which
+    /// need particular ones, like the PC-98, should also call `with_icw4`.
+    pub const fn with_layout(mut self, layout: PortLayout) -> ChainedPics<P> {
+        self.set_layout(layout);
+        self
+    }
+
+    /// Do the work of `with_layout`.  Our builders take `self` by value, so
+    /// `FixedChainedPics` calls this instead.
+    const fn set_layout(&mut self, layout: PortLayout) {
+        assert!(layout.cascade < 8, "PIC1 only has lines 0 through 7");
+        self.pics[0].command = layout.pic1_command;
+        self.pics[0].data = layout.pic1_data;
+        self.pics[1].command = layout.pic2_command;
+        self.pics[1].data = layout.pic2_data;
+        if self.cascade.is_some() {
+            self.cascade = Some(layout.cascade);
+        }
+    }
+
+    /// Is there a PIC2 chained to PIC1?
+    pub fn is_cascaded(&self) -> bool {
+        self.cascade.is_some()
+    }
+
+    /// The PICs we actually have: just PIC1 in single mode, or both.
+    fn pic_count(&self) -> usize {
+        if self.cascade.is_some() {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Like `with_port_io`, but check that the offsets make sense first.
+    /// See `ChainedPics::validate_offsets`.
+    pub unsafe fn try_with_port_io(
+        offset1: u8,
+        offset2: u8,
+        io: P,
+    ) -> Result<ChainedPics<P>, VectorLayoutError> {
+        ChainedPics::validate_offsets(offset1, offset2)?;
+        Ok(ChainedPics::with_port_io(offset1, offset2, io))
+    }
+
+    /// Like `single_with_port_io`, but check that the offset makes sense
+    /// first.  See `ChainedPics::validate_single_offset`.
+    pub unsafe fn try_single_with_port_io(
+        offset: u8,
+        io: P,
+    ) -> Result<ChainedPics<P>, VectorLayoutError> {
+        ChainedPics::validate_single_offset(offset)?;
+        Ok(ChainedPics::single_with_port_io(offset, io))
+    }
+
+    /// Choose how we pause between the writes of our initialization
+    /// sequence.  The default is `Delay::Port80`.
+    pub const fn with_delay(mut self, delay: Delay) -> ChainedPics<P> {
+        self.delay = delay;
+        self
+    }
+
+    /// Configure automatic EOI mode for PIC1 and PIC2, which takes effect
+    /// the next time we're initialized.  A PIC in this mode clears its
+    /// in-service bit as soon as the processor acknowledges an interrupt,
+    /// so `notify_end_of_interrupt` doesn't need to send it anything.  The
+    /// catch is that nothing stops a handler from being interrupted by
+    /// another request on the same line.
+    pub const fn with_auto_eoi(mut self, pic1: bool, pic2: bool) -> ChainedPics<P> {
+        self.set_auto_eoi(pic1, pic2);
+        self
+    }
+
+    /// Do the work of `with_auto_eoi`.
+    const fn set_auto_eoi(&mut self, pic1: bool, pic2: bool) {
+        self.pics[0].mode = self.pics[0].mode.with_auto_eoi(pic1);
+        self.pics[1].mode = self.pics[1].mode.with_auto_eoi(pic2);
+    }
+
+    /// Configure special fully nested mode for PIC1, which takes effect the
+    /// next time we're initialized.  Normally, while an interrupt from PIC2
+    /// is in service, PIC1 blocks everything else from PIC2, even if it has
+    /// a higher priority, because it all arrives on the same line.  In
+    /// special fully nested mode, PIC1 lets it through, and
+    /// `notify_end_of_interrupt` only ends the interrupt on PIC1's cascade
+    /// line once PIC2 has nothing left in service.
+    pub const fn with_special_fully_nested(mut self, enabled: bool) -> ChainedPics<P> {
+        self.set_special_fully_nested(enabled);
+        self
+    }
+
+    /// Do the work of `with_special_fully_nested`.
+    const fn set_special_fully_nested(&mut self, enabled: bool) {
+        self.pics[0].mode = self.pics[0].mode.with_special_fully_nested(enabled);
+    }
+
+    /// Choose the mode bytes for PIC1 and PIC2, which take effect the next
+    /// time we're initialized.  This replaces anything set by
+    /// `with_auto_eoi`.  In single mode, `pic2` is ignored.
+    pub const fn with_icw4(mut self, pic1: Icw4, pic2: Icw4) -> ChainedPics<P> {
+        self.set_icw4(pic1, pic2);
+        self
+    }
+
+    /// Do the work of `with_icw4`.
+    const fn set_icw4(&mut self, pic1: Icw4, pic2: Icw4) {
+        self.pics[0].mode = pic1;
+        self.pics[1].mode = pic2;
+    }
+
+    /// The mode bytes we'll send PIC1 and PIC2 when we next initialize
+    /// them.
+    pub fn icw4(&self) -> [Icw4; 2] {
+        [self.pics[0].mode, self.pics[1].mode]
+    }
+
+    /// Get a reference to our port backend.
+    pub fn port_io(&self) -> &P {
+        &self.io
+    }
+
+    /// Get a mutable reference to our port backend.
+    pub fn port_io_mut(&mut self) -> &mut P {
+        &mut self.io
+    }
+
+    /// Initialize both our PICs.  We initialize them together, at the same
+    /// time, because it's traditional to do so, and because I/O operations
+    /// might not be instantaneous on older processors.
+    pub unsafe fn initialize(&mut self) {
+        self.internal_initialize_with_mask(None, None)
+    }
+
+    /// Initialize both our PICs, as with `initialize`, but load the
+    /// provided masks rather than keeping the current ones.
+    pub unsafe fn initialize_with_mask(&mut self, pic1_mask: u8, pic2_mask: u8) {
+        self.internal_initialize_with_mask(Some(pic1_mask), Some(pic2_mask))
+    }
+
+    unsafe fn internal_initialize_with_mask(
+        &mut self,
+        pic1_mask: Option<u8>,
+        pic2_mask: Option<u8>,
+    ) {
+        // Save our original interrupt masks, because I'm too lazy to
+        // figure out reasonable values.  We'll restore these when we're
+        // done.
+        let saved_mask1 = self.pics[0].read_mask(&mut self.io);
+        let saved_mask2 = match self.cascade {
+            Some(_) => self.pics[1].read_mask(&mut self.io),
+            None => 0xFF,
+        };
+
+        // Work out everything we're going to send, and keep a copy, since
+        // the PICs can't tell us afterwards.  In single mode, we skip ICW3
+        // and leave PIC2's entry blank, since we never send it anything.
+        let pic1_mask = pic1_mask.unwrap_or(saved_mask1);
+        let pic2_mask = pic2_mask.unwrap_or(saved_mask2);
+        let shadow = match self.cascade {
+            Some(cascade) => [
+                self.pics[0].begin_initialize(CMD_INIT, 1 << cascade, pic1_mask),
+                self.pics[1].begin_initialize(CMD_INIT, cascade, pic2_mask),
+            ],
+            None => [
+                self.pics[0].begin_initialize(CMD_INIT_SINGLE, 0, pic1_mask),
+                PicShadow {
+                    icw1: 0,
+                    icw2: 0,
+                    icw3: 0,
+                    icw4: 0,
+                    mask: 0xFF,
+                },
+            ],
+        };
+        self.shadow = Some(shadow);
+        let count = self.pic_count();
+        Pic::initialize_chain(
+            &mut self.io,
+            self.delay,
+            self.pics[..count].iter().zip(shadow.iter()),
+        );
+    }
+
+    /// Find out which of our PICs are actually there, by writing test masks
+    /// and seeing whether they read back.  A missing PIC usually reads as
+    /// 0xFF.  Like `self_test`, this briefly unmasks lines, so run it with
+    /// interrupts disabled.  In single mode, we only look for PIC1.
+    pub unsafe fn probe(&mut self) -> PicPresence {
+        if !self.pics[0].test_mask_readback(&mut self.io) {
+            PicPresence::Absent
+        } else if self.cascade.is_none() || !self.pics[1].test_mask_readback(&mut self.io) {
+            PicPresence::MasterOnly
+        } else {
+            PicPresence::Cascaded
+        }
+    }
+
+    /// Everything we sent the PICs the last time we initialized them, or
+    /// `None` if we haven't yet.  In single mode, PIC2's entry is all
+    /// zeros, except for a mask with every line masked.
+    pub fn shadow(&self) -> Option<[PicShadow; 2]> {
+        self.shadow
+    }
+
+    /// Check that the PICs seem to be working, and return a report which
+    /// can be printed at boot.  We write some distinctive masks and read
+    /// them back, check that the in-service registers make sense, and then
+    /// put the original masks back.  The test briefly unmasks lines, so
+    /// run it with interrupts disabled, outside of any interrupt handler.
+    /// In single mode, there's no PIC2 to test, so its mask readback
+    /// always passes.
+    pub unsafe fn self_test(&mut self) -> SelfTestReport {
+        let mask_readback = [
+            self.pics[0].test_mask_readback(&mut self.io),
+            self.cascade.is_none() || self.pics[1].test_mask_readback(&mut self.io),
+        ];
+
+        // We're not in an interrupt handler, so nothing should be in
+        // service.  A PIC which isn't there at all typically reads as 0xFF,
+        // and no real chip has all eight lines waiting at once.
+        let irr = self.read_irr();
+        let isr = self.read_isr();
+        let irr_sane = irr as u8 != 0xFF && (self.cascade.is_none() || (irr >> 8) as u8 != 0xFF);
+        SelfTestReport {
+            shadow: self.shadow,
+            mask_readback,
+            irr,
+            irr_sane,
+            isr,
+            isr_sane: isr == 0,
+        }
+    }
+
+    /// Take a snapshot of everything needed to put the PICs back the way
+    /// they are now, for example after resuming from suspend.  The PICs
+    /// can't report their offsets or modes, so those come from what we last
+    /// told them; the masks are read back from the hardware.
+    pub unsafe fn save_state(&mut self) -> PicState {
+        PicState {
+            offsets: [self.pics[0].offset, self.pics[1].offset],
+            modes: self.icw4(),
+            masks: self.read_masks(),
+            special_mask_mode: self.pics[0].special_mask,
+            rotate_on_auto_eoi: self.pics[0].rotate_on_auto_eoi,
+            elcr: None,
+        }
+    }
+
+    /// Like `save_state`, but also save the ELCR.  Only use this on
+    /// chipsets which have one.
+    pub unsafe fn save_state_with_elcr(&mut self) -> PicState {
+        let mut state = self.save_state();
+        state.elcr = Some(Elcr::with_port_io(&mut self.io).read());
+        state
+    }
+
+    /// Reinitialize the PICs from a snapshot taken by `save_state`, and
+    /// adopt its offsets and modes as our own.  The offsets are checked
+    /// first, as with `try_new` (or `try_new_single` in single mode), and
+    /// if they don't make sense, we return an error without touching the
+    /// PICs.
+    pub unsafe fn restore_state(&mut self, state: &PicState) -> Result<(), VectorLayoutError> {
+        match self.cascade {
+            Some(_) => ChainedPics::validate_offsets(state.offsets[0], state.offsets[1])?,
+            None => ChainedPics::validate_single_offset(state.offsets[0])?,
+        }
+        for (pic, (&offset, &mode)) in self
+            .pics
+            .iter_mut()
+            .zip(state.offsets.iter().zip(state.modes.iter()))
+        {
+            pic.offset = offset;
+            pic.mode = mode;
+        }
+        if let Some(levels) = state.elcr {
+            // These came from the hardware, so write them back as they
+            // were, even if the firmware did something odd.
+            Elcr::with_port_io(&mut self.io).write_unchecked(levels);
+        }
+        self.internal_initialize_with_mask(Some(state.masks[0]), Some(state.masks[1]));
+        if state.special_mask_mode {
+            self.enter_special_mask_mode();
+        }
+        if state.rotate_on_auto_eoi {
+            self.set_rotate_on_auto_eoi(true);
+        }
+        Ok(())
+    }
+
+    /// Get the PICs out of the way, so that we can switch to the local APIC
+    /// and IOAPIC instead.  We mask every line, end any interrupts which
+    /// are still in service (even in special mask mode, where masked lines
+    /// would otherwise ignore us), and then move both PICs to vectors 0xF0
+    /// through 0xFF.  That way, if either of them raises a spurious
+    /// interrupt after all, it won't land on one of the processor's
+    /// exception handlers.
+    pub unsafe fn disable(&mut self) {
+        self.write_masks(0xFF, 0xFF);
+        if self.cascade.is_some() {
+            self.pics[1].drain(&mut self.io);
+        }
+        self.pics[0].drain(&mut self.io);
+        self.pics[0].offset = DISABLED_PIC_1_OFFSET;
+        self.pics[1].offset = DISABLED_PIC_2_OFFSET;
+        self.internal_initialize_with_mask(Some(0xFF), Some(0xFF));
+    }
+
+    /// Read the interrupt request registers of both PICs, which show the
+    /// IRQ lines that have been raised but not yet delivered.  Bit `n` of
+    /// the result corresponds to IRQ `n`.  In single mode, the high byte is
+    /// always zero.
+    pub unsafe fn read_irr(&mut self) -> u16 {
+        let irr1 = self.pics[0].read_irr(&mut self.io);
+        let irr2 = match self.cascade {
+            Some(_) => self.pics[1].read_irr(&mut self.io),
+            None => 0,
+        };
+        (irr2 as u16) << 8 | irr1 as u16
+    }
+
+    /// Read the in-service registers of both PICs, which show the IRQ
+    /// lines that have been delivered but not yet acknowledged with an end
+    /// of interrupt.  Bit `n` of the result corresponds to IRQ `n`.  In
+    /// single mode, the high byte is always zero.
+    pub unsafe fn read_isr(&mut self) -> u16 {
+        let isr1 = self.pics[0].read_isr(&mut self.io);
+        let isr2 = match self.cascade {
+            Some(_) => self.pics[1].read_isr(&mut self.io),
+            None => 0,
+        };
+        (isr2 as u16) << 8 | isr1 as u16
+    }
+
+    /// Is this interrupt a spurious IRQ 7 or IRQ 15?  When an IRQ line is
+    /// deasserted before the processor acknowledges it, the PIC reports
+    /// the lowest-priority line on the affected chip instead, without
+    /// marking it as in service.  We detect that by checking the
+    /// in-service register.
+    ///
+    /// A PIC in automatic EOI mode never marks anything as in service, so
+    /// we can't tell its spurious interrupts from real ones.  For those
+    /// PICs, we always return `false`, and your handler will occasionally
+    /// run when its device has nothing to say.
+    pub unsafe fn is_spurious_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) -> bool {
+        let interrupt_id = interrupt_id.into().number();
+        let count = self.pic_count();
+        let io = &mut self.io;
+        self.pics[..count]
+            .iter()
+            .any(|p| p.is_spurious_interrupt(io, interrupt_id))
+    }
+
+    /// Check whether this interrupt is spurious, and if it is, tell the
+    /// PICs whatever they need to know about it.  A spurious IRQ 7 must not
+    /// be acknowledged at all, but a spurious IRQ 15 still went through
+    /// PIC1's cascade line, so PIC1 (and only PIC1) needs an end of
+    /// interrupt.  Like `is_spurious_interrupt`, this can't spot spurious
+    /// interrupts from a PIC in automatic EOI mode.
+    ///
+    /// Returns `true` if the interrupt was spurious, in which case the
+    /// handler should return immediately without calling
+    /// `notify_end_of_interrupt`.
+    pub unsafe fn handle_spurious_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) -> bool {
+        let interrupt_id = interrupt_id.into().number();
+        self.pics[0].is_spurious_interrupt(&mut self.io, interrupt_id)
+            || (self.cascade.is_some()
+                && self.pics[0].handle_spurious_slave_interrupt(
+                    &mut self.io,
+                    &self.pics[1],
+                    interrupt_id,
+                ))
+    }
+
+    /// Read the interrupt masks of both PICs, as `[pic1_mask, pic2_mask]`.
+    /// A set bit means that the corresponding IRQ line is masked.  In
+    /// single mode, PIC2's lines are all reported as masked.
+    pub unsafe fn read_masks(&mut self) -> [u8; 2] {
+        [
+            self.pics[0].read_mask(&mut self.io),
+            match self.cascade {
+                Some(_) => self.pics[1].read_mask(&mut self.io),
+                None => 0xFF,
+            },
+        ]
+    }
+
+    /// Write the interrupt masks of both PICs.  If any line on PIC2 is
+    /// left enabled, the cascade line on PIC1 is unmasked as well, since
+    /// otherwise nothing from PIC2 would ever reach the processor.  In
+    /// single mode, `pic2_mask` is ignored.
+    pub unsafe fn write_masks(&mut self, mut pic1_mask: u8, pic2_mask: u8) {
+        match self.cascade {
+            Some(cascade) => {
+                if pic2_mask != 0xFF {
+                    pic1_mask &= !(1 << cascade);
+                }
+                self.pics[0].write_mask(&mut self.io, pic1_mask);
+                self.pics[1].write_mask(&mut self.io, pic2_mask);
+            }
+            None => self.pics[0].write_mask(&mut self.io, pic1_mask),
+        }
+    }
+
+    /// Mask the specified IRQ line, so that the PICs stop forwarding it to
+    /// the processor.  In single mode, IRQs 8 through 15 are ignored.  As
+    /// with `write_masks`, the cascade line on PIC1 stays unmasked while
+    /// any line on PIC2 is enabled, so masking it does nothing until every
+    /// line on PIC2 has been masked.
+    pub unsafe fn mask_irq(&mut self, irq: Irq) {
+        let (pic, line) = (irq.pic_index(), irq.line());
+        if pic >= self.pic_count() {
+            return;
+        }
+        if pic == 0 && Some(line) == self.cascade && self.pics[1].read_mask(&mut self.io) != 0xFF {
+            return;
+        }
+        let mask = self.pics[pic].read_mask(&mut self.io);
+        self.pics[pic].write_mask(&mut self.io, mask | (1 << line));
+    }
+
+    /// Unmask the specified IRQ line.  Unmasking a line on PIC2 also
+    /// unmasks the cascade line on PIC1.  In single mode, IRQs 8 through
+    /// 15 are ignored.
+    pub unsafe fn unmask_irq(&mut self, irq: Irq) {
+        let (pic, line) = (irq.pic_index(), irq.line());
+        if pic >= self.pic_count() {
+            return;
+        }
+        let mask = self.pics[pic].read_mask(&mut self.io);
+        self.pics[pic].write_mask(&mut self.io, mask & !(1 << line));
+        if let (1, Some(cascade)) = (pic, self.cascade) {
+            let mask = self.pics[0].read_mask(&mut self.io);
+            self.pics[0].write_mask(&mut self.io, mask & !(1 << cascade));
+        }
+    }
+
+    /// Is the specified IRQ line currently masked?  In single mode, IRQs 8
+    /// through 15 don't exist, so they're always masked.
+    pub unsafe fn is_masked(&mut self, irq: Irq) -> bool {
+        if irq.pic_index() >= self.pic_count() {
+            return true;
+        }
+        self.pics[irq.pic_index()].read_mask(&mut self.io) & (1 << irq.line()) != 0
+    }
+
+    /// The interrupt vector on which the specified IRQ line is delivered,
+    /// or `None` if we're a single PIC and the line doesn't exist.
+    pub fn irq_to_vector(&self, irq: Irq) -> Option<InterruptVector> {
+        if irq.pic_index() >= self.pic_count() {
+            return None;
+        }
+        let offset = self.pics[irq.pic_index()].offset;
+        Some(InterruptVector::new(offset.wrapping_add(irq.line())))
+    }
+
+    /// The IRQ line which is delivered on the specified interrupt vector,
+    /// or `None` if the vector doesn't belong to either PIC.
+    pub fn vector_to_irq<V: Into<InterruptVector>>(&self, interrupt_id: V) -> Option<Irq> {
+        let interrupt_id = interrupt_id.into().number();
+        self.pics[..self.pic_count()]
+            .iter()
+            .enumerate()
+            .find(|&(_, p)| p.handles_interrupt(interrupt_id))
+            .and_then(|(i, p)| Irq::new(8 * i as u8 + (interrupt_id - p.offset)))
+    }
+
+    /// Do we handle this interrupt?
+    pub fn handles_interrupt<V: Into<InterruptVector>>(&self, interrupt_id: V) -> bool {
+        let interrupt_id = interrupt_id.into().number();
+        self.pics[..self.pic_count()]
+            .iter()
+            .any(|p| p.handles_interrupt(interrupt_id))
+    }
+
+    /// Figure out which (if any) PICs in our chain need to know about this
+    /// interrupt.  This is tricky, because all interrupts from `pics[1]`
+    /// get chained through `pics[0]`.  If PIC1 is in special fully nested
+    /// mode, we check PIC2's in-service register first, and leave PIC1's
+    /// cascade line in service until PIC2 has finished with everything.
+    pub unsafe fn notify_end_of_interrupt<V: Into<InterruptVector>>(&mut self, interrupt_id: V) {
+        self.internal_notify_end_of_interrupt(interrupt_id.into(), false, false)
+    }
+
+    /// Like `notify_end_of_interrupt`, but tell each PIC exactly which of
+    /// its lines we've finished with, rather than letting it assume we
+    /// mean the highest-priority one in service.  This is what you want if
+    /// you've changed priorities or are using special mask mode.
+    pub unsafe fn notify_specific_end_of_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) {
+        self.internal_notify_end_of_interrupt(interrupt_id.into(), true, false)
+    }
+
+    /// Like `notify_end_of_interrupt`, but also make this interrupt's line
+    /// the lowest priority on its PIC, so that devices which share a PIC
+    /// get round-robin service.
+    pub unsafe fn notify_end_of_interrupt_and_rotate<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) {
+        self.internal_notify_end_of_interrupt(interrupt_id.into(), false, true)
+    }
+
+    /// Like `notify_specific_end_of_interrupt`, but also make this
+    /// interrupt's line the lowest priority on its PIC.
+    pub unsafe fn notify_specific_end_of_interrupt_and_rotate<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) {
+        self.internal_notify_end_of_interrupt(interrupt_id.into(), true, true)
+    }
+
+    /// Send the right kind of end of interrupt to every PIC involved in
+    /// delivering this interrupt.  Only the PIC which owns the line rotates
+    /// its priorities; if that's PIC2, PIC1 just hears about its cascade
+    /// line, so that the slave as a whole keeps its place.
+    unsafe fn internal_notify_end_of_interrupt(
+        &mut self,
+        interrupt_id: InterruptVector,
+        specific: bool,
+        rotate: bool,
+    ) {
+        let irq = match self.vector_to_irq(interrupt_id) {
+            Some(irq) => irq,
+            None => return,
+        };
+        match (irq.pic_index(), self.cascade) {
+            (1, Some(cascade)) => self.pics[0].end_of_cascaded_interrupt(
+                &mut self.io,
+                &self.pics[1],
+                cascade,
+                irq.line(),
+                specific,
+                rotate,
+            ),
+            _ => {
+                let eoi = Eoi::new(specific, rotate, irq.line());
+                self.pics[0].end_of_interrupt(&mut self.io, eoi)
+            }
+        }
+    }
+
+    /// Ask the PICs for the highest-priority pending IRQ, without waiting
+    /// for an interrupt.  This is useful when interrupts are disabled, for
+    /// example early in boot or while writing a crash dump.  If PIC1
+    /// reports its cascade line, we go on to poll PIC2.
+    ///
+    /// Polling acknowledges the IRQ just as the processor would, so the
+    /// returned line is now in service and needs an end of interrupt, which
+    /// you can send by passing `pics.irq_to_vector(irq)` to
+    /// `notify_end_of_interrupt`.
+    pub unsafe fn poll(&mut self) -> Option<Irq> {
+        let line = self.pics[0].poll(&mut self.io)?;
+        if Some(line) != self.cascade {
+            return Irq::new(line);
+        }
+        let line = self.pics[0].poll_slave(&mut self.io, &self.pics[1])?;
+        Irq::new(8 + line)
+    }
+
+    /// Put both PICs into special mask mode.  Normally, a PIC won't deliver
+    /// anything of lower priority than the highest interrupt in service.
+    /// In special mask mode, it delivers everything that isn't masked, so a
+    /// slow handler can mask its own line and let lower-priority interrupts
+    /// through.  Use `notify_specific_end_of_interrupt` while in this mode,
+    /// because the PICs can't work out which interrupt a non-specific EOI
+    /// refers to.
+    pub unsafe fn enter_special_mask_mode(&mut self) {
+        let count = self.pic_count();
+        for pic in self.pics[..count].iter_mut() {
+            pic.set_special_mask_mode(&mut self.io, true);
+        }
+    }
+
+    /// Return both PICs to normal mask mode.
+    pub unsafe fn leave_special_mask_mode(&mut self) {
+        let count = self.pic_count();
+        for pic in self.pics[..count].iter_mut() {
+            pic.set_special_mask_mode(&mut self.io, false);
+        }
+    }
+
+    /// Enter special mask mode until the returned guard is dropped.  The
+    /// guard dereferences to our `ChainedPics`, so it can be used to mask
+    /// lines and send EOIs in the meantime.
+    pub unsafe fn special_mask_mode(&mut self) -> SpecialMaskModeGuard<'_, P> {
+        self.enter_special_mask_mode();
+        SpecialMaskModeGuard { pics: self }
+    }
+
+    /// Make the specified IRQ line the lowest priority on its PIC.  The
+    /// other lines on that PIC follow it in order, wrapping around, so
+    /// the next line up becomes the highest priority.
+    pub unsafe fn set_lowest_priority(&mut self, irq: Irq) {
+        if irq.pic_index() >= self.pic_count() {
+            return;
+        }
+        self.pics[irq.pic_index()].set_lowest_priority(&mut self.io, irq.line());
+    }
+
+    /// Turn automatic priority rotation on or off for PICs in automatic
+    /// EOI mode.  When it's on, each interrupt's line becomes the lowest
+    /// priority on its PIC as soon as it's acknowledged.
+    pub unsafe fn set_rotate_on_auto_eoi(&mut self, enabled: bool) {
+        let count = self.pic_count();
+        for pic in self.pics[..count].iter_mut() {
+            pic.set_rotate_on_auto_eoi(&mut self.io, enabled);
+        }
+    }
+}
+
+/// A pair of chained PICs whose offsets are fixed at compile time.  Offsets
+/// which `ChainedPics::validate_offsets` would reject fail to compile, and
+/// the vector arithmetic is available as `const fn`s which don't need an
+/// instance at all.
+///
+/// Everything which only looks at our configuration is available through
+/// `Deref` to the underlying `ChainedPics`, and everything else is
+/// forwarded to it, apart from the methods which would change our offsets
+/// behind our back.  `restore_state` keeps our offsets, and `disable` is
+/// only available on the `ChainedPics` returned by `into_inner`.  The
+/// `special_mask_mode` guard would hand out the whole `ChainedPics`, so use
+/// `enter_special_mask_mode` and `leave_special_mask_mode` instead.
+pub struct FixedChainedPics<const OFFSET1: u8, const OFFSET2: u8, P = X86PortIo> {
+    pics: ChainedPics<P>,
+}
+
+impl<const OFFSET1: u8, const OFFSET2: u8> FixedChainedPics<OFFSET1, OFFSET2> {
+    /// Create a new interface for the standard PIC1 and PIC2 controllers.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const unsafe fn new() -> FixedChainedPics<OFFSET1, OFFSET2> {
+        FixedChainedPics::with_port_io(X86PortIo)
+    }
+}
+
+impl<const OFFSET1: u8, const OFFSET2: u8, P> FixedChainedPics<OFFSET1, OFFSET2, P> {
+    /// Evaluating this fails to compile if our offsets are invalid.
+    const VALID_OFFSETS: () = match ChainedPics::validate_offsets(OFFSET1, OFFSET2) {
+        Ok(()) => (),
+        Err(VectorLayoutError::Overlapping) => panic!("PIC vector ranges overlap"),
+        Err(VectorLayoutError::ReservedVector(_)) => {
+            panic!("PIC offset overlaps the processor's exception vectors")
+        }
+        Err(VectorLayoutError::Misaligned(_)) => panic!("PIC offset is not a multiple of 8"),
     };
-    (pic_1, pic_2)
+
+    /// Do we handle this interrupt?
+    pub const fn handles_interrupt(interrupt_id: InterruptVector) -> bool {
+        let () = Self::VALID_OFFSETS;
+        let interrupt_id = interrupt_id.number();
+        interrupt_id.wrapping_sub(OFFSET1) < 8 || interrupt_id.wrapping_sub(OFFSET2) < 8
+    }
+
+    /// The interrupt vector on which the specified IRQ line is delivered.
+    /// Unlike `ChainedPics::irq_to_vector`, this can't fail, because we're
+    /// never in single mode.
+    pub const fn vector_for_irq(irq: Irq) -> InterruptVector {
+        let () = Self::VALID_OFFSETS;
+        let offset = if irq.pic_index() == 0 {
+            OFFSET1
+        } else {
+            OFFSET2
+        };
+        InterruptVector::new(offset + irq.line())
+    }
+
+    /// The IRQ line which is delivered on the specified interrupt vector,
+    /// or `None` if the vector doesn't belong to either PIC.
+    pub const fn vector_to_irq(interrupt_id: InterruptVector) -> Option<Irq> {
+        let () = Self::VALID_OFFSETS;
+        let interrupt_id = interrupt_id.number();
+        if interrupt_id.wrapping_sub(OFFSET1) < 8 {
+            Irq::new(interrupt_id - OFFSET1)
+        } else if interrupt_id.wrapping_sub(OFFSET2) < 8 {
+            Irq::new(8 + interrupt_id - OFFSET2)
+        } else {
+            None
+        }
+    }
+}
+
+impl<const OFFSET1: u8, const OFFSET2: u8, P: PortIo> FixedChainedPics<OFFSET1, OFFSET2, P> {
+    /// Create a new interface for the standard PIC1 and PIC2 controllers,
+    /// using the specified backend to reach their I/O ports.
+    pub const unsafe fn with_port_io(io: P) -> FixedChainedPics<OFFSET1, OFFSET2, P> {
+        let () = Self::VALID_OFFSETS;
+        FixedChainedPics {
+            pics: ChainedPics::with_port_io(OFFSET1, OFFSET2, io),
+        }
+    }
+
+    /// See `ChainedPics::with_delay`.
+    pub const fn with_delay(mut self, delay: Delay) -> FixedChainedPics<OFFSET1, OFFSET2, P> {
+        self.pics.delay = delay;
+        self
+    }
+
+    /// See `ChainedPics::with_auto_eoi`.
+    pub const fn with_auto_eoi(
+        mut self,
+        pic1: bool,
+        pic2: bool,
+    ) -> FixedChainedPics<OFFSET1, OFFSET2, P> {
+        self.pics.set_auto_eoi(pic1, pic2);
+        self
+    }
+
+    /// See `ChainedPics::with_special_fully_nested`.
+    pub const fn with_special_fully_nested(
+        mut self,
+        enabled: bool,
+    ) -> FixedChainedPics<OFFSET1, OFFSET2, P> {
+        self.pics.set_special_fully_nested(enabled);
+        self
+    }
+
+    /// See `ChainedPics::with_icw4`.
+    pub const fn with_icw4(
+        mut self,
+        pic1: Icw4,
+        pic2: Icw4,
+    ) -> FixedChainedPics<OFFSET1, OFFSET2, P> {
+        self.pics.set_icw4(pic1, pic2);
+        self
+    }
+
+    /// See `ChainedPics::with_layout`.
+    pub const fn with_layout(
+        mut self,
+        layout: PortLayout,
+    ) -> FixedChainedPics<OFFSET1, OFFSET2, P> {
+        self.pics.set_layout(layout);
+        self
+    }
+
+    /// Give up our compile-time offsets, and return the underlying
+    /// `ChainedPics`, for example to `disable` it.
+    pub fn into_inner(self) -> ChainedPics<P> {
+        self.pics
+    }
+}
+
+impl<const OFFSET1: u8, const OFFSET2: u8, P: PortIo> Deref
+    for FixedChainedPics<OFFSET1, OFFSET2, P>
+{
+    type Target = ChainedPics<P>;
+
+    fn deref(&self) -> &ChainedPics<P> {
+        &self.pics
+    }
+}
+
+impl<const OFFSET1: u8, const OFFSET2: u8, P: PortIo> FixedChainedPics<OFFSET1, OFFSET2, P> {
+    /// See `ChainedPics::port_io_mut`.
+    pub fn port_io_mut(&mut self) -> &mut P {
+        self.pics.port_io_mut()
+    }
+
+    /// See `ChainedPics::initialize`.
+    pub unsafe fn initialize(&mut self) {
+        self.pics.initialize()
+    }
+
+    /// See `ChainedPics::initialize_with_mask`.
+    pub unsafe fn initialize_with_mask(&mut self, pic1_mask: u8, pic2_mask: u8) {
+        self.pics.initialize_with_mask(pic1_mask, pic2_mask)
+    }
+
+    /// See `ChainedPics::probe`.
+    pub unsafe fn probe(&mut self) -> PicPresence {
+        self.pics.probe()
+    }
+
+    /// See `ChainedPics::self_test`.
+    pub unsafe fn self_test(&mut self) -> SelfTestReport {
+        self.pics.self_test()
+    }
+
+    /// See `ChainedPics::save_state`.
+    pub unsafe fn save_state(&mut self) -> PicState {
+        self.pics.save_state()
+    }
+
+    /// See `ChainedPics::save_state_with_elcr`.
+    pub unsafe fn save_state_with_elcr(&mut self) -> PicState {
+        self.pics.save_state_with_elcr()
+    }
+
+    /// Reinitialize the PICs from a snapshot taken by `save_state`, as
+    /// with `ChainedPics::restore_state`.  Our offsets are fixed, so we keep
+    /// them, whatever the snapshot says, and there's nothing to go wrong.
+    pub unsafe fn restore_state(&mut self, state: &PicState) {
+        let mut state = *state;
+        state.offsets = [OFFSET1, OFFSET2];
+        self.pics
+            .restore_state(&state)
+            .expect("our offsets were checked at compile time")
+    }
+
+    /// See `ChainedPics::read_irr`.
+    pub unsafe fn read_irr(&mut self) -> u16 {
+        self.pics.read_irr()
+    }
+
+    /// See `ChainedPics::read_isr`.
+    pub unsafe fn read_isr(&mut self) -> u16 {
+        self.pics.read_isr()
+    }
+
+    /// See `ChainedPics::is_spurious_interrupt`.
+    pub unsafe fn is_spurious_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) -> bool {
+        self.pics.is_spurious_interrupt(interrupt_id)
+    }
+
+    /// See `ChainedPics::handle_spurious_interrupt`.
+    pub unsafe fn handle_spurious_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) -> bool {
+        self.pics.handle_spurious_interrupt(interrupt_id)
+    }
+
+    /// See `ChainedPics::read_masks`.
+    pub unsafe fn read_masks(&mut self) -> [u8; 2] {
+        self.pics.read_masks()
+    }
+
+    /// See `ChainedPics::write_masks`.
+    pub unsafe fn write_masks(&mut self, pic1_mask: u8, pic2_mask: u8) {
+        self.pics.write_masks(pic1_mask, pic2_mask)
+    }
+
+    /// See `ChainedPics::mask_irq`.
+    pub unsafe fn mask_irq(&mut self, irq: Irq) {
+        self.pics.mask_irq(irq)
+    }
+
+    /// See `ChainedPics::unmask_irq`.
+    pub unsafe fn unmask_irq(&mut self, irq: Irq) {
+        self.pics.unmask_irq(irq)
+    }
+
+    /// See `ChainedPics::is_masked`.
+    pub unsafe fn is_masked(&mut self, irq: Irq) -> bool {
+        self.pics.is_masked(irq)
+    }
+
+    /// See `ChainedPics::notify_end_of_interrupt`.
+    pub unsafe fn notify_end_of_interrupt<V: Into<InterruptVector>>(&mut self, interrupt_id: V) {
+        self.pics.notify_end_of_interrupt(interrupt_id)
+    }
+
+    /// See `ChainedPics::notify_specific_end_of_interrupt`.
+    pub unsafe fn notify_specific_end_of_interrupt<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) {
+        self.pics.notify_specific_end_of_interrupt(interrupt_id)
+    }
+
+    /// See `ChainedPics::notify_end_of_interrupt_and_rotate`.
+    pub unsafe fn notify_end_of_interrupt_and_rotate<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) {
+        self.pics.notify_end_of_interrupt_and_rotate(interrupt_id)
+    }
+
+    /// See `ChainedPics::notify_specific_end_of_interrupt_and_rotate`.
+    pub unsafe fn notify_specific_end_of_interrupt_and_rotate<V: Into<InterruptVector>>(
+        &mut self,
+        interrupt_id: V,
+    ) {
+        self.pics
+            .notify_specific_end_of_interrupt_and_rotate(interrupt_id)
+    }
+
+    /// See `ChainedPics::poll`.
+    pub unsafe fn poll(&mut self) -> Option<Irq> {
+        self.pics.poll()
+    }
+
+    /// See `ChainedPics::enter_special_mask_mode`.
+    pub unsafe fn enter_special_mask_mode(&mut self) {
+        self.pics.enter_special_mask_mode()
+    }
+
+    /// See `ChainedPics::leave_special_mask_mode`.
+    pub unsafe fn leave_special_mask_mode(&mut self) {
+        self.pics.leave_special_mask_mode()
+    }
+
+    /// See `ChainedPics::set_lowest_priority`.
+    pub unsafe fn set_lowest_priority(&mut self, irq: Irq) {
+        self.pics.set_lowest_priority(irq)
+    }
+
+    /// See `ChainedPics::set_rotate_on_auto_eoi`.
+    pub unsafe fn set_rotate_on_auto_eoi(&mut self, enabled: bool) {
+        self.pics.set_rotate_on_auto_eoi(enabled)
+    }
+}
+
+/// Keeps a pair of PICs in special mask mode, and returns them to normal
+/// mode when dropped.  Created by `ChainedPics::special_mask_mode`.
+pub struct SpecialMaskModeGuard<'a, P: PortIo + 'a> {
+    pics: &'a mut ChainedPics<P>,
+}
+
+impl<'a, P: PortIo> Deref for SpecialMaskModeGuard<'a, P> {
+    type Target = ChainedPics<P>;
+
+    fn deref(&self) -> &ChainedPics<P> {
+        self.pics
+    }
+}
+
+impl<'a, P: PortIo> DerefMut for SpecialMaskModeGuard<'a, P> {
+    fn deref_mut(&mut self) -> &mut ChainedPics<P> {
+        self.pics
+    }
+}
+
+impl<'a, P: PortIo> Drop for SpecialMaskModeGuard<'a, P> {
+    fn drop(&mut self) {
+        unsafe { self.pics.leave_special_mask_mode() }
+    }
 }
 
 /// Command sent to begin PIC initialization.
 const CMD_INIT: u8 = 0x11;
 
+/// Command sent to begin initializing a PIC with nothing chained to it,
+/// which won't expect the cascade configuration byte.
+const CMD_INIT_SINGLE: u8 = CMD_INIT | 0x02;
+
 /// Command sent to acknowledge an interrupt.
 const CMD_END_OF_INTERRUPT: u8 = 0x20;
 
-// The mode in which we want to run our PICs.
-const MODE_8086: u8 = 0x01;
+/// Command sent to acknowledge a specific interrupt, which goes in the low
+/// three bits.
+const CMD_SPECIFIC_END_OF_INTERRUPT: u8 = 0x60;
+
+/// Command sent to acknowledge an interrupt and rotate priorities.
+const CMD_ROTATE_ON_END_OF_INTERRUPT: u8 = 0xA0;
+
+/// Command sent to acknowledge a specific interrupt and rotate priorities.
+const CMD_ROTATE_ON_SPECIFIC_END_OF_INTERRUPT: u8 = 0xE0;
+
+/// Command sent to make a line, which goes in the low three bits, the
+/// lowest priority.
+const CMD_SET_PRIORITY: u8 = 0xC0;
+
+/// Command sent to rotate priorities on each automatic EOI.
+const CMD_SET_ROTATE_ON_AUTO_EOI: u8 = 0x80;
+
+/// Command sent to stop rotating priorities on automatic EOIs.
+const CMD_CLEAR_ROTATE_ON_AUTO_EOI: u8 = 0x00;
+
+/// Command sent to enter special mask mode.
+const CMD_SET_SPECIAL_MASK: u8 = 0x68;
+
+/// Command sent to leave special mask mode.
+const CMD_CLEAR_SPECIAL_MASK: u8 = 0x48;
+
+/// Command sent to make the next read of the command port acknowledge the
+/// highest-priority pending interrupt and report it.
+const CMD_POLL: u8 = 0x0C;
+
+/// Set in the response to `CMD_POLL` if an interrupt was pending, in which
+/// case the low three bits say which.
+const POLL_INTERRUPT: u8 = 0x80;
+
+/// Command sent to make the next read of the command port return the
+/// interrupt request register.
+const CMD_READ_IRR: u8 = 0x0A;
+
+/// Command sent to make the next read of the command port return the
+/// in-service register.
+const CMD_READ_ISR: u8 = 0x0B;
+
+/// The masks `self_test` and `probe` write and read back.
+const SELF_TEST_MASKS: [u8; 2] = [0xA5, 0x5A];
+
+/// The first vector which isn't reserved for processor exceptions.
+const FIRST_FREE_VECTOR: u8 = 0x20;
+
+/// Where `disable` moves PIC1's interrupts, well clear of the processor's
+/// exceptions.
+const DISABLED_PIC_1_OFFSET: u8 = 0xF0;
+
+/// Where `disable` moves PIC2's interrupts.
+const DISABLED_PIC_2_OFFSET: u8 = 0xF8;
+
+/// The port on which we select the IMCR.
+const IMCR_SELECT_PORT: u16 = 0x22;
+
+/// The port on which we access the IMCR once it's selected.
+const IMCR_DATA_PORT: u16 = 0x23;
+
+/// The value which selects the IMCR.
+const IMCR_REGISTER: u8 = 0x70;
+
+/// Set in the IMCR to route interrupts through the APIC.
+const IMCR_APIC_MODE: u8 = 0x01;
+
+/// The ports of the two ELCRs, for PIC1's and PIC2's lines respectively.
+const ELCR_PORTS: [u16; 2] = [0x4D0, 0x4D1];
+
+/// The IRQs which must always be edge-triggered: 0, 1, 2, 8 and 13.
+const ELCR_EDGE_ONLY: u16 = 0x2107;
+
+/// The unused I/O port we write to when we need a short delay.
+const WAIT_PORT: u16 = 0x80;
 
 /// An individual PIC chip.  This is not exported, because we always access
-/// it through `Pics` below.
+/// it through `ChainedPics` or `CascadedPics`.
 struct Pic {
     /// The base offset to which our interrupts are mapped.
     offset: u8,
 
+    /// The mode byte we send at the end of initialization.
+    mode: Icw4,
+
+    /// Are we in special mask mode?
+    special_mask: bool,
+
+    /// Do we rotate priorities on automatic EOIs?
+    rotate_on_auto_eoi: bool,
+
     /// The processor I/O port on which we send commands.
-    command: Port<u8>,
+    command: u16,
 
     /// The processor I/O port on which we send and receive data.
-    data: Port<u8>,
+    data: u16,
 }
 
 impl Pic {
+    /// A PIC on the specified ports, which we'll map to `offset` and run in
+    /// 8086 mode.
+    const fn new(offset: u8, command: u16, data: u16) -> Pic {
+        Pic {
+            offset,
+            mode: Icw4::new(),
+            special_mask: false,
+            rotate_on_auto_eoi: false,
+            command,
+            data,
+        }
+    }
+
     /// Are we in change of handling the specified interrupt?
     /// (Each PIC handles 8 interrupts.)
     fn handles_interrupt(&self, interrupt_id: u8) -> bool {
-        self.offset <= interrupt_id && interrupt_id < self.offset + 8
+        interrupt_id.wrapping_sub(self.offset) < 8
+    }
+
+    /// Read our interrupt request register, which has a bit set for each
+    /// line that has been raised but not yet delivered.
+    unsafe fn read_irr<P: PortIo>(&self, io: &mut P) -> u8 {
+        self.write_command(io, CMD_READ_IRR);
+        io.read(self.command)
+    }
+
+    /// Read our in-service register, which has a bit set for each
+    /// interrupt we have delivered but not yet seen an end of interrupt for.
+    unsafe fn read_isr<P: PortIo>(&self, io: &mut P) -> u8 {
+        self.write_command(io, CMD_READ_ISR);
+        io.read(self.command)
+    }
+
+    /// Is this a spurious interrupt?  These always arrive on our
+    /// lowest-priority line, 7, but aren't marked as in service.  In
+    /// automatic EOI mode, nothing is ever marked as in service, so we
+    /// can't tell, and assume that the interrupt is real.
+    unsafe fn is_spurious_interrupt<P: PortIo>(&self, io: &mut P, interrupt_id: u8) -> bool {
+        interrupt_id == self.offset.wrapping_add(7)
+            && !self.auto_eoi()
+            && self.read_isr(io) & (1 << 7) == 0
+    }
+
+    /// Poll for the highest-priority pending line, acknowledging it if
+    /// there is one.
+    unsafe fn poll<P: PortIo>(&self, io: &mut P) -> Option<u8> {
+        self.write_command(io, CMD_POLL);
+        let response = io.read(self.command);
+        if response & POLL_INTERRUPT != 0 {
+            Some(response & 0x07)
+        } else {
+            None
+        }
+    }
+
+    /// Read our interrupt mask.
+    unsafe fn read_mask<P: PortIo>(&self, io: &mut P) -> u8 {
+        io.read(self.data)
+    }
+
+    /// Write some distinctive masks and check that we read them back, then
+    /// put our original mask back.
+    unsafe fn test_mask_readback<P: PortIo>(&self, io: &mut P) -> bool {
+        let saved_mask = self.read_mask(io);
+        let mut ok = true;
+        for &pattern in SELF_TEST_MASKS.iter() {
+            self.write_mask(io, pattern);
+            ok &= self.read_mask(io) == pattern;
+        }
+        self.write_mask(io, saved_mask);
+        ok
+    }
+
+    /// Replace our interrupt mask.
+    unsafe fn write_mask<P: PortIo>(&self, io: &mut P, mask: u8) {
+        self.write_data(io, mask)
     }
 
     /// Notify us that an interrupt has been handled and that we're ready
-    /// for more.
-    unsafe fn end_of_interrupt(&mut self) {
-        self.command.write(CMD_END_OF_INTERRUPT);
+    /// for more.  In automatic EOI mode, there's nothing to do.
+    unsafe fn end_of_interrupt<P: PortIo>(&self, io: &mut P, eoi: Eoi) {
+        if !self.auto_eoi() {
+            self.write_command(io, eoi.command());
+        }
+    }
+
+    /// Notify us, as the master, that an interrupt from `slave` has been
+    /// handled.  In special fully nested mode, `slave` may still have other
+    /// interrupts in service, which all share our line, so we wait until
+    /// it's finished with all of them.
+    unsafe fn end_of_slave_interrupt<P: PortIo>(&self, io: &mut P, slave: &Pic, eoi: Eoi) {
+        if self.mode.special_fully_nested() && slave.read_isr(io) != 0 {
+            return;
+        }
+        self.end_of_interrupt(io, eoi);
+    }
+
+    /// Notify us, as the master, and `slave`, which is chained to our
+    /// `input`, that an interrupt on the slave's `line` has been handled.
+    /// Only the slave rotates its priorities; we just hear about the
+    /// cascade input, so that the slave as a whole keeps its place.
+    unsafe fn end_of_cascaded_interrupt<P: PortIo>(
+        &self,
+        io: &mut P,
+        slave: &Pic,
+        input: u8,
+        line: u8,
+        specific: bool,
+        rotate: bool,
+    ) {
+        slave.end_of_interrupt(io, Eoi::new(specific, rotate, line));
+        self.end_of_slave_interrupt(io, slave, Eoi::new(specific, false, input));
+    }
+
+    /// Check whether an interrupt from `slave` is spurious.  If it is, it
+    /// still went through us, as the master, so we need an end of
+    /// interrupt for the cascade input.
+    unsafe fn handle_spurious_slave_interrupt<P: PortIo>(
+        &self,
+        io: &mut P,
+        slave: &Pic,
+        interrupt_id: u8,
+    ) -> bool {
+        if slave.is_spurious_interrupt(io, interrupt_id) {
+            self.end_of_slave_interrupt(io, slave, Eoi::NonSpecific);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Poll `slave`, after we, as the master, reported its cascade input.
+    /// If the slave has changed its mind, we've already put the cascade
+    /// input in service, so we end it again.
+    unsafe fn poll_slave<P: PortIo>(&self, io: &mut P, slave: &Pic) -> Option<u8> {
+        let line = slave.poll(io);
+        if line.is_none() {
+            self.end_of_slave_interrupt(io, slave, Eoi::NonSpecific);
+        }
+        line
+    }
+
+    /// End every interrupt we still have in service.  We send a specific
+    /// EOI for each one, because in special mask mode, a non-specific EOI
+    /// leaves masked lines in service.  We do this even in automatic EOI
+    /// mode, just in case.
+    unsafe fn drain<P: PortIo>(&self, io: &mut P) {
+        let isr = self.read_isr(io);
+        for line in 0..8 {
+            if isr & (1 << line) != 0 {
+                self.write_command(io, Eoi::Specific(line).command());
+            }
+        }
+    }
+
+    /// Work out everything we're about to send during initialization.
+    /// `icw1` is `CMD_INIT`, or `CMD_INIT_SINGLE` if nothing is chained to
+    /// us, `icw3` is our cascade configuration, and `mask` is loaded once
+    /// we're done.  Initialization takes us out of special mask mode and
+    /// stops priority rotation, so we forget about those now.
+    fn begin_initialize(&mut self, icw1: u8, icw3: u8, mask: u8) -> PicShadow {
+        self.special_mask = false;
+        self.rotate_on_auto_eoi = false;
+        PicShadow {
+            icw1,
+            icw2: self.offset,
+            icw3,
+            icw4: self.mode.bits(),
+            mask,
+        }
+    }
+
+    /// Send each PIC in `chain` the initialization sequence from
+    /// `begin_initialize`.  We initialize them together, one byte at a
+    /// time, because it's traditional to do so, and we pause after each
+    /// byte because I/O operations might not be instantaneous on older
+    /// processors.  A PIC in single mode doesn't expect ICW3 at all.
+    unsafe fn initialize_chain<'a, P, I>(io: &mut P, delay: Delay, chain: I)
+    where
+        P: PortIo,
+        I: Iterator<Item = (&'a Pic, &'a PicShadow)> + Clone,
+    {
+        // Tell each PIC that we're going to send it an initialization
+        // sequence on its data port.
+        for (pic, shadow) in chain.clone() {
+            pic.write_command(io, shadow.icw1);
+            delay.wait(io);
+        }
+
+        // Byte 1: Set up our base offsets.
+        for (pic, shadow) in chain.clone() {
+            pic.write_data(io, shadow.icw2);
+            delay.wait(io);
+        }
+
+        // Byte 2: Configure chaining between masters and slaves.
+        for (pic, shadow) in chain.clone() {
+            if shadow.icw1 != CMD_INIT_SINGLE {
+                pic.write_data(io, shadow.icw3);
+                delay.wait(io);
+            }
+        }
+
+        // Byte 3: Set our mode.
+        for (pic, shadow) in chain.clone() {
+            pic.write_data(io, shadow.icw4);
+            delay.wait(io);
+        }
+
+        // Load our masks.
+        for (pic, shadow) in chain {
+            pic.write_mask(io, shadow.mask);
+        }
+    }
+
+    /// Make the specified line our lowest priority.
+    unsafe fn set_lowest_priority<P: PortIo>(&self, io: &mut P, line: u8) {
+        self.write_command(io, CMD_SET_PRIORITY | line);
+    }
+
+    /// Enter or leave special mask mode.
+    unsafe fn set_special_mask_mode<P: PortIo>(&mut self, io: &mut P, enabled: bool) {
+        self.special_mask = enabled;
+        if enabled {
+            self.write_command(io, CMD_SET_SPECIAL_MASK);
+        } else {
+            self.write_command(io, CMD_CLEAR_SPECIAL_MASK);
+        }
+    }
+
+    /// Turn priority rotation in automatic EOI mode on or off.
+    unsafe fn set_rotate_on_auto_eoi<P: PortIo>(&mut self, io: &mut P, enabled: bool) {
+        self.rotate_on_auto_eoi = enabled;
+        if enabled {
+            self.write_command(io, CMD_SET_ROTATE_ON_AUTO_EOI);
+        } else {
+            self.write_command(io, CMD_CLEAR_ROTATE_ON_AUTO_EOI);
+        }
+    }
+
+    /// Do we end our own interrupts automatically?
+    fn auto_eoi(&self) -> bool {
+        self.mode.auto_eoi()
+    }
+
+    /// Send a byte to our command port.
+    unsafe fn write_command<P: PortIo>(&self, io: &mut P, command: u8) {
+        io.write(self.command, command)
+    }
+
+    /// Send a byte to our data port.
+    unsafe fn write_data<P: PortIo>(&self, io: &mut P, data: u8) {
+        io.write(self.data, data)
+    }
+}
+
+/// The different kinds of end of interrupt we can send to a single PIC.
+#[derive(Clone, Copy, Debug)]
+enum Eoi {
+    /// End the highest-priority interrupt in service.
+    NonSpecific,
+    /// End the interrupt on the specified line.
+    Specific(u8),
+    /// End the highest-priority interrupt in service, and make its line
+    /// the lowest priority.
+    RotateNonSpecific,
+    /// End the interrupt on the specified line, and make it the lowest
+    /// priority.
+    RotateSpecific(u8),
+}
+
+impl Eoi {
+    /// Pick the kind of end of interrupt to send for `line`.
+    fn new(specific: bool, rotate: bool, line: u8) -> Eoi {
+        match (specific, rotate) {
+            (false, false) => Eoi::NonSpecific,
+            (true, false) => Eoi::Specific(line),
+            (false, true) => Eoi::RotateNonSpecific,
+            (true, true) => Eoi::RotateSpecific(line),
+        }
+    }
+
+    /// The OCW2 command byte for this end of interrupt.
+    fn command(self) -> u8 {
+        match self {
+            Eoi::NonSpecific => CMD_END_OF_INTERRUPT,
+            Eoi::Specific(line) => CMD_SPECIFIC_END_OF_INTERRUPT | line,
+            Eoi::RotateNonSpecific => CMD_ROTATE_ON_END_OF_INTERRUPT,
+            Eoi::RotateSpecific(line) => CMD_ROTATE_ON_SPECIFIC_END_OF_INTERRUPT | line,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::boxed::Box;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use {ChainedPics, Delay, Elcr, ElcrError, Irq, PortIo, TriggerMode};
+
+    /// Ignores every write, and reads as an empty bus.
+    struct EmptyBus;
+
+    impl PortIo for EmptyBus {
+        unsafe fn read(&mut self, _port: u16) -> u8 {
+            0xFF
+        }
+
+        unsafe fn write(&mut self, _port: u16, _value: u8) {}
+    }
+
+    /// A pair of ELCRs, and nothing else.
+    struct FakeElcr {
+        levels: [u8; 2],
+    }
+
+    impl PortIo for FakeElcr {
+        unsafe fn read(&mut self, port: u16) -> u8 {
+            match port {
+                0x4D0 => self.levels[0],
+                0x4D1 => self.levels[1],
+                _ => 0xFF,
+            }
+        }
+
+        unsafe fn write(&mut self, port: u16, value: u8) {
+            match port {
+                0x4D0 => self.levels[0] = value,
+                0x4D1 => self.levels[1] = value,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn elcr_keeps_edge_only_irqs_edge_triggered() {
+        let mut elcr = unsafe { Elcr::with_port_io(FakeElcr { levels: [0, 0] }) };
+        for &number in [0, 1, 2, 8, 13].iter() {
+            let irq = Irq::new(number).unwrap();
+            unsafe {
+                assert_eq!(elcr.write(1 << number), Err(ElcrError::EdgeOnly(irq)));
+                assert_eq!(
+                    elcr.set_trigger_mode(irq, TriggerMode::Level),
+                    Err(ElcrError::EdgeOnly(irq))
+                );
+            }
+        }
+        unsafe {
+            assert_eq!(elcr.read(), 0x0000);
+            assert_eq!(elcr.write(0xDEF8), Ok(()));
+            assert_eq!(elcr.read(), 0xDEF8);
+        }
+    }
+
+    #[test]
+    fn elcr_tolerates_firmware_setting_edge_only_irqs() {
+        // The firmware left the timer level-triggered.
+        let mut elcr = unsafe {
+            Elcr::with_port_io(FakeElcr {
+                levels: [0x01, 0x00],
+            })
+        };
+        let irq11 = Irq::new(11).unwrap();
+        unsafe {
+            assert_eq!(elcr.set_trigger_mode(irq11, TriggerMode::Level), Ok(()));
+            assert_eq!(elcr.read(), 0x0801);
+            assert_eq!(elcr.trigger_mode(irq11), TriggerMode::Level);
+            assert_eq!(elcr.set_trigger_mode(Irq::TIMER, TriggerMode::Edge), Ok(()));
+            assert_eq!(elcr.read(), 0x0800);
+        }
+    }
+
+    #[test]
+    fn busy_wait_can_capture_state() {
+        let waits: &'static AtomicUsize = Box::leak(Box::new(AtomicUsize::new(0)));
+        let wait = Box::leak(Box::new(move || {
+            waits.fetch_add(1, Ordering::Relaxed);
+        }));
+        let mut pics = unsafe { ChainedPics::with_port_io(0x20, 0x28, EmptyBus) }
+            .with_delay(Delay::BusyWait(wait));
+        unsafe { pics.initialize() };
+        assert_eq!(waits.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn self_test_flags_a_missing_pic() {
+        let mut pics =
+            unsafe { ChainedPics::with_port_io(0x20, 0x28, EmptyBus) }.with_delay(Delay::Immediate);
+        unsafe { pics.initialize() };
+        let report = unsafe { pics.self_test() };
+        assert_eq!(report.irr, 0xFFFF);
+        assert!(!report.irr_sane);
+        assert!(!report.passed());
     }
 }
@@ -0,0 +1,850 @@
+//! A software model of a cascaded pair of 8259A PICs, for testing PIC code
+//! without real hardware.  `ChainedModel` implements `PortIo`, so it can be
+//! handed to `ChainedPics::with_port_io` in place of the real x86 ports.
+//! Tests can then raise IRQ lines, check which vector the processor would
+//! receive, and watch what our EOIs do to the in-service registers.
+//!
+//! The model only uses `core`, so it works equally well from a `std` unit
+//! test on the host or from inside a kernel.  It follows the 8259A
+//! datasheet closely enough for our purposes, including the ICW state
+//! machine, priority resolution and rotation, specific and non-specific
+//! EOIs, automatic EOI mode, special mask mode, special fully nested mode
+//! and polling.  It does not try to model timing, level-triggered inputs or
+//! the MCS-80/85 call sequence.
+
+use irq::Irq;
+use port::PortIo;
+use PortLayout;
+
+/// ICW1 bit: this is an initialization command word.
+const ICW1_INIT: u8 = 0x10;
+
+/// ICW1 bit: this chip is alone, so there will be no ICW3.
+const ICW1_SINGLE: u8 = 0x02;
+
+/// ICW1 bit: an ICW4 will follow.
+const ICW1_ICW4: u8 = 0x01;
+
+/// ICW4 bit: automatic end of interrupt.
+const ICW4_AUTO_EOI: u8 = 0x02;
+
+/// ICW4 bit: special fully nested mode.
+const ICW4_SPECIAL_FULLY_NESTED: u8 = 0x10;
+
+/// OCW3 bit: this is an OCW3 rather than an OCW2.
+const OCW3_SELECT: u8 = 0x08;
+
+/// OCW3 bit: poll command.
+const OCW3_POLL: u8 = 0x04;
+
+/// OCW3 bit: the RIS bit is valid.
+const OCW3_READ_REGISTER: u8 = 0x02;
+
+/// OCW3 bit: read the ISR rather than the IRR.
+const OCW3_READ_ISR: u8 = 0x01;
+
+/// OCW3 bit: the SMM bit is valid.
+const OCW3_SPECIAL_MASK_SELECT: u8 = 0x40;
+
+/// OCW3 bit: enter special mask mode.
+const OCW3_SPECIAL_MASK: u8 = 0x20;
+
+/// Which byte a chip expects next on its data port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Expect {
+    /// Nothing special: data port writes set the mask.
+    Mask,
+    /// The vector base.
+    Icw2,
+    /// The cascade configuration.
+    Icw3,
+    /// The mode.
+    Icw4,
+}
+
+/// A software model of a single 8259A.
+#[derive(Clone, Debug)]
+pub struct Model8259 {
+    expect: Expect,
+    initialized: bool,
+    icw1: u8,
+    icw2: u8,
+    icw3: u8,
+    icw4: u8,
+    imr: u8,
+    irr: u8,
+    isr: u8,
+    lowest_priority: u8,
+    read_isr: bool,
+    poll: bool,
+    special_mask: bool,
+    rotate_on_auto_eoi: bool,
+}
+
+impl Default for Model8259 {
+    fn default() -> Model8259 {
+        Model8259::new()
+    }
+}
+
+impl Model8259 {
+    /// Create a chip in its power-on state, waiting for ICW1.
+    pub fn new() -> Model8259 {
+        Model8259 {
+            expect: Expect::Mask,
+            initialized: false,
+            icw1: 0,
+            icw2: 0,
+            icw3: 0,
+            icw4: 0,
+            imr: 0,
+            irr: 0,
+            isr: 0,
+            lowest_priority: 7,
+            read_isr: false,
+            poll: false,
+            special_mask: false,
+            rotate_on_auto_eoi: false,
+        }
+    }
+
+    /// Has this chip received a complete initialization sequence?
+    pub fn is_initialized(&self) -> bool {
+        self.initialized && self.expect == Expect::Mask
+    }
+
+    /// The base vector programmed by ICW2.
+    pub fn offset(&self) -> u8 {
+        self.icw2 & 0xF8
+    }
+
+    /// The last ICW1 we received.
+    pub fn icw1(&self) -> u8 {
+        self.icw1
+    }
+
+    /// The last ICW3 we received, or 0 if it was skipped.
+    pub fn icw3(&self) -> u8 {
+        self.icw3
+    }
+
+    /// The last ICW4 we received, or 0 if it was skipped.
+    pub fn icw4(&self) -> u8 {
+        self.icw4
+    }
+
+    /// The interrupt mask register.
+    pub fn imr(&self) -> u8 {
+        self.imr
+    }
+
+    /// The interrupt request register.
+    pub fn irr(&self) -> u8 {
+        self.irr
+    }
+
+    /// The in-service register.
+    pub fn isr(&self) -> u8 {
+        self.isr
+    }
+
+    /// The line which currently has the lowest priority.  This is 7 unless
+    /// priorities have been rotated.
+    pub fn lowest_priority(&self) -> u8 {
+        self.lowest_priority
+    }
+
+    /// Are we in special mask mode?
+    pub fn special_mask_mode(&self) -> bool {
+        self.special_mask
+    }
+
+    /// Are we in automatic EOI mode?
+    pub fn auto_eoi(&self) -> bool {
+        self.icw4 & ICW4_AUTO_EOI != 0
+    }
+
+    /// Are we in special fully nested mode?
+    pub fn special_fully_nested(&self) -> bool {
+        self.icw4 & ICW4_SPECIAL_FULLY_NESTED != 0
+    }
+
+    /// Raise the specified input line (0 through 7).  Inputs are edge
+    /// triggered, so this latches a request in the IRR.
+    pub fn raise(&mut self, line: u8) {
+        assert!(line < 8, "line {} is out of range", line);
+        self.irr |= 1 << line;
+    }
+
+    /// Drop a request which has not been acknowledged yet, as happens when
+    /// a device deasserts its line too early.  If the processor has already
+    /// seen our INT output, this is what produces a spurious IRQ 7.
+    pub fn lower(&mut self, line: u8) {
+        assert!(line < 8, "line {} is out of range", line);
+        self.irr &= !(1 << line);
+    }
+
+    /// The lines, in order from highest to lowest priority.
+    fn priority_order(&self) -> [u8; 8] {
+        let mut order = [0; 8];
+        for (i, line) in order.iter_mut().enumerate() {
+            *line = (self.lowest_priority + 1 + i as u8) % 8;
+        }
+        order
+    }
+
+    /// Does the specified line have a slave attached?
+    fn is_cascade_line(&self, line: u8) -> bool {
+        self.icw1 & ICW1_SINGLE == 0 && self.icw3 & (1 << line) != 0
+    }
+
+    /// Work out which line we would deliver next, if any.  `irr` is our
+    /// IRR with any slave outputs mixed in.
+    fn pending_line(&self, irr: u8) -> Option<u8> {
+        let requests = irr & !self.imr;
+        for &line in self.priority_order().iter() {
+            let bit = 1 << line;
+            if self.isr & bit != 0 && !self.special_mask {
+                // An in-service slave input may be interrupted again by a
+                // higher-priority request on that slave in special fully
+                // nested mode.  Otherwise, this level and everything below
+                // it are blocked.
+                if self.special_fully_nested() && self.is_cascade_line(line) && requests & bit != 0
+                {
+                    return Some(line);
+                }
+                return None;
+            }
+            if requests & bit != 0 && self.isr & bit == 0 {
+                return Some(line);
+            }
+        }
+        None
+    }
+
+    /// Would we currently assert our INT output?
+    fn int(&self, irr: u8) -> bool {
+        self.is_initialized() && self.pending_line(irr).is_some()
+    }
+
+    /// Acknowledge the specified line, as the first INTA pulse does.
+    fn acknowledge_line(&mut self, line: u8) {
+        let bit = 1 << line;
+        self.irr &= !bit;
+        if self.auto_eoi() {
+            if self.rotate_on_auto_eoi {
+                self.lowest_priority = line;
+            }
+        } else {
+            self.isr |= bit;
+        }
+    }
+
+    /// Respond to an INTA sequence, returning the line we deliver.  If
+    /// nothing is pending any more, we deliver a spurious IRQ 7 without
+    /// marking it as in service.
+    fn acknowledge(&mut self, irr: u8) -> u8 {
+        match self.pending_line(irr) {
+            Some(line) => {
+                self.acknowledge_line(line);
+                line
+            }
+            None => 7,
+        }
+    }
+
+    /// Find the in-service line which a non-specific EOI would end.  This
+    /// is the one with the highest priority, except that in special mask
+    /// mode, the datasheet says that masked lines are left alone.
+    fn highest_in_service(&self) -> Option<u8> {
+        let candidates = if self.special_mask {
+            self.isr & !self.imr
+        } else {
+            self.isr
+        };
+        self.priority_order()
+            .iter()
+            .cloned()
+            .find(|&line| candidates & (1 << line) != 0)
+    }
+
+    /// Handle a byte written to our command port.
+    fn write_command(&mut self, value: u8) {
+        if value & ICW1_INIT != 0 {
+            // ICW1 resets almost everything and starts the sequence.  The
+            // datasheet doesn't list the ISR among the things it clears,
+            // so anything in service stays in service.
+            self.icw1 = value;
+            self.icw3 = 0;
+            self.icw4 = 0;
+            self.imr = 0;
+            self.irr = 0;
+            self.lowest_priority = 7;
+            self.read_isr = false;
+            self.poll = false;
+            self.special_mask = false;
+            self.rotate_on_auto_eoi = false;
+            self.initialized = true;
+            self.expect = Expect::Icw2;
+        } else if value & OCW3_SELECT != 0 {
+            self.write_ocw3(value);
+        } else {
+            self.write_ocw2(value);
+        }
+    }
+
+    /// Handle an OCW2: EOIs and priority rotation.
+    fn write_ocw2(&mut self, value: u8) {
+        let level = value & 0x07;
+        match value >> 5 {
+            // Non-specific EOI, optionally rotating.
+            0b001 | 0b101 => {
+                if let Some(line) = self.highest_in_service() {
+                    self.isr &= !(1 << line);
+                    if value >> 5 == 0b101 {
+                        self.lowest_priority = line;
+                    }
+                }
+            }
+            // Specific EOI, optionally rotating.
+            0b011 | 0b111 => {
+                self.isr &= !(1 << level);
+                if value >> 5 == 0b111 {
+                    self.lowest_priority = level;
+                }
+            }
+            // Rotate in automatic EOI mode, clear and set.
+            0b000 => self.rotate_on_auto_eoi = false,
+            0b100 => self.rotate_on_auto_eoi = true,
+            // Set priority.
+            0b110 => self.lowest_priority = level,
+            // No operation.
+            _ => {}
+        }
+    }
+
+    /// Handle an OCW3: register reads, polling and special mask mode.
+    fn write_ocw3(&mut self, value: u8) {
+        if value & OCW3_SPECIAL_MASK_SELECT != 0 {
+            self.special_mask = value & OCW3_SPECIAL_MASK != 0;
+        }
+        if value & OCW3_READ_REGISTER != 0 {
+            self.read_isr = value & OCW3_READ_ISR != 0;
+        }
+        self.poll = value & OCW3_POLL != 0;
+    }
+
+    /// Handle a read from our command port.  `irr` is our IRR with any
+    /// slave outputs mixed in.
+    fn read_command(&mut self, irr: u8) -> u8 {
+        if self.poll {
+            // A poll command turns the next read into an acknowledge.
+            self.poll = false;
+            match self.pending_line(irr) {
+                Some(line) => {
+                    self.acknowledge_line(line);
+                    0x80 | line
+                }
+                None => 0,
+            }
+        } else if self.read_isr {
+            self.isr
+        } else {
+            irr
+        }
+    }
+
+    /// Handle a byte written to our data port.
+    fn write_data(&mut self, value: u8) {
+        let single = self.icw1 & ICW1_SINGLE != 0;
+        let needs_icw4 = self.icw1 & ICW1_ICW4 != 0;
+        self.expect = match self.expect {
+            Expect::Mask => {
+                self.imr = value;
+                Expect::Mask
+            }
+            Expect::Icw2 => {
+                self.icw2 = value;
+                if !single {
+                    Expect::Icw3
+                } else if needs_icw4 {
+                    Expect::Icw4
+                } else {
+                    Expect::Mask
+                }
+            }
+            Expect::Icw3 => {
+                self.icw3 = value;
+                if needs_icw4 {
+                    Expect::Icw4
+                } else {
+                    Expect::Mask
+                }
+            }
+            Expect::Icw4 => {
+                self.icw4 = value;
+                Expect::Mask
+            }
+        };
+    }
+
+    /// Handle a read from our data port.
+    fn read_data(&self) -> u8 {
+        self.imr
+    }
+}
+
+/// A software model of a master 8259A and a slave whose output is wired to
+/// one of the master's inputs, laid out as a `PortLayout` says.  Reads
+/// from any other port return 0xFF, as they would from an empty bus, and
+/// writes to them are ignored.
+#[derive(Clone, Debug)]
+pub struct ChainedModel {
+    master: Model8259,
+    slave: Model8259,
+    layout: PortLayout,
+}
+
+impl Default for ChainedModel {
+    fn default() -> ChainedModel {
+        ChainedModel::new()
+    }
+}
+
+impl ChainedModel {
+    /// Create a pair of chips in their power-on state, in the standard
+    /// PC/AT setup: the master on ports 0x20 and 0x21, and the slave on
+    /// ports 0xA0 and 0xA1, wired to the master's IRQ 2 input.
+    pub fn new() -> ChainedModel {
+        ChainedModel::with_layout(PortLayout::PC_AT)
+    }
+
+    /// Create a pair of chips in their power-on state, on the ports given
+    /// by `layout`, with the slave wired to `layout.cascade`.  Panics if
+    /// that isn't between 0 and 7.
+    pub fn with_layout(layout: PortLayout) -> ChainedModel {
+        assert!(layout.cascade < 8, "the master only has inputs 0 through 7");
+        ChainedModel {
+            master: Model8259::new(),
+            slave: Model8259::new(),
+            layout,
+        }
+    }
+
+    /// The master chip.
+    pub fn master(&self) -> &Model8259 {
+        &self.master
+    }
+
+    /// The slave chip.
+    pub fn slave(&self) -> &Model8259 {
+        &self.slave
+    }
+
+    /// Raise the specified IRQ line.
+    pub fn raise_irq(&mut self, irq: Irq) {
+        self.chip_mut(irq).raise(irq.line());
+    }
+
+    /// Drop an unacknowledged request on the specified IRQ line.  See
+    /// `Model8259::lower`.
+    pub fn lower_irq(&mut self, irq: Irq) {
+        self.chip_mut(irq).lower(irq.line());
+    }
+
+    /// The chip which handles the specified IRQ line.
+    fn chip_mut(&mut self, irq: Irq) -> &mut Model8259 {
+        if irq.pic_index() == 0 {
+            &mut self.master
+        } else {
+            &mut self.slave
+        }
+    }
+
+    /// The master's IRR as its inputs see it, including the slave's INT
+    /// output on the cascade line if the master is expecting a slave there.
+    fn master_inputs(&self) -> u8 {
+        let cascade = 1 << self.layout.cascade;
+        if !self.master.is_cascade_line(self.layout.cascade) {
+            self.master.irr
+        } else if self.slave.int(self.slave.irr) {
+            self.master.irr | cascade
+        } else {
+            self.master.irr & !cascade
+        }
+    }
+
+    /// Is the master asserting INT to the processor?
+    pub fn interrupt_pending(&self) -> bool {
+        self.master.int(self.master_inputs())
+    }
+
+    /// The vector the processor would receive if it acknowledged an
+    /// interrupt now, or `None` if nothing is pending.
+    pub fn pending_vector(&self) -> Option<u8> {
+        let line = self.master.pending_line(self.master_inputs())?;
+        if !self.master.is_cascade_line(line) {
+            Some(self.master.offset() + line)
+        } else if self.slave_answers(line) {
+            let slave_line = self.slave.pending_line(self.slave.irr).unwrap_or(7);
+            Some(self.slave.offset() + slave_line)
+        } else {
+            Some(0xFF)
+        }
+    }
+
+    /// Would our slave respond to the master putting `line` on the
+    /// cascade bus?
+    fn slave_answers(&self, line: u8) -> bool {
+        self.slave.is_initialized() && self.slave.icw3 & 0x07 == line
+    }
+
+    /// Perform an interrupt acknowledge cycle, as the processor does when
+    /// it takes an interrupt, and return the vector it receives.  If the
+    /// request went away before this is called, the result is a spurious
+    /// IRQ 7 or IRQ 15, just as on real hardware.
+    pub fn acknowledge(&mut self) -> u8 {
+        let inputs = self.master_inputs();
+        let line = self.master.acknowledge(inputs);
+        if self.master.is_cascade_line(line) {
+            if self.slave_answers(line) {
+                let irr = self.slave.irr;
+                let slave_line = self.slave.acknowledge(irr);
+                return self.slave.offset() + slave_line;
+            }
+            // Nobody drives the data bus.
+            return 0xFF;
+        }
+        self.master.offset() + line
+    }
+}
+
+impl PortIo for ChainedModel {
+    unsafe fn read(&mut self, port: u16) -> u8 {
+        let layout = self.layout;
+        if port == layout.pic1_command {
+            let inputs = self.master_inputs();
+            self.master.read_command(inputs)
+        } else if port == layout.pic1_data {
+            self.master.read_data()
+        } else if port == layout.pic2_command {
+            let irr = self.slave.irr;
+            self.slave.read_command(irr)
+        } else if port == layout.pic2_data {
+            self.slave.read_data()
+        } else {
+            0xFF
+        }
+    }
+
+    unsafe fn write(&mut self, port: u16, value: u8) {
+        let layout = self.layout;
+        if port == layout.pic1_command {
+            self.master.write_command(value)
+        } else if port == layout.pic1_data {
+            self.master.write_data(value)
+        } else if port == layout.pic2_command {
+            self.slave.write_command(value)
+        } else if port == layout.pic2_data {
+            self.slave.write_data(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::string::ToString;
+    use super::ChainedModel;
+    use irq::Irq;
+    use port::PortIo;
+    use {ChainedPics, Delay, Icw4, PortLayout, VectorLayoutError};
+
+    /// A pair of PICs on `model`, initialized at 0x20 and 0x28 with every
+    /// line unmasked.
+    fn initialized(model: &mut ChainedModel) -> ChainedPics<&mut ChainedModel> {
+        let mut pics =
+            unsafe { ChainedPics::with_port_io(0x20, 0x28, model) }.with_delay(Delay::Immediate);
+        unsafe { pics.initialize_with_mask(0x00, 0x00) };
+        pics
+    }
+
+    #[test]
+    fn initialize_runs_the_icw_sequence() {
+        let mut model = ChainedModel::new();
+        initialized(&mut model);
+        assert!(model.master().is_initialized());
+        assert_eq!(model.master().offset(), 0x20);
+        assert_eq!(model.master().icw3(), 0x04);
+        assert_eq!(model.master().icw4(), 0x01);
+        assert!(model.slave().is_initialized());
+        assert_eq!(model.slave().offset(), 0x28);
+        assert_eq!(model.slave().icw3(), 0x02);
+        assert_eq!(model.slave().icw4(), 0x01);
+    }
+
+    #[test]
+    fn initialize_keeps_the_in_service_register() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        let model = pics.port_io_mut();
+        model.raise_irq(Irq::TIMER);
+        assert_eq!(model.acknowledge(), 0x20);
+        unsafe { pics.initialize() };
+        assert_eq!(pics.port_io().master().isr(), 0x01);
+    }
+
+    #[test]
+    fn non_specific_eoi_skips_masked_lines_in_special_mask_mode() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        let model = pics.port_io_mut();
+        model.raise_irq(Irq::COM1);
+        assert_eq!(model.acknowledge(), 0x24);
+        unsafe {
+            pics.enter_special_mask_mode();
+            pics.mask_irq(Irq::COM1);
+        }
+        let model = pics.port_io_mut();
+        model.raise_irq(Irq::new(5).unwrap());
+        assert_eq!(model.acknowledge(), 0x25);
+        assert_eq!(model.master().isr(), 0x30);
+        unsafe { pics.notify_end_of_interrupt(0x25) };
+        assert_eq!(pics.port_io().master().isr(), 0x10);
+    }
+
+    #[test]
+    fn pc98_layout() {
+        let mut model = ChainedModel::with_layout(PortLayout::PC98);
+        let mut pics = unsafe { ChainedPics::with_port_io(0x20, 0x28, &mut model) }
+            .with_layout(PortLayout::PC98)
+            .with_icw4(Icw4::PC98_MASTER, Icw4::PC98_SLAVE)
+            .with_delay(Delay::Immediate);
+        unsafe { pics.initialize_with_mask(0x00, 0x00) };
+        let model = pics.port_io_mut();
+        assert_eq!(model.master().icw4(), 0x1D);
+        assert_eq!(model.slave().icw4(), 0x09);
+        assert_eq!(model.master().icw3(), 0x80);
+        assert_eq!(model.slave().icw3(), 0x07);
+        model.raise_irq(Irq::new(9).unwrap());
+        assert_eq!(model.acknowledge(), 0x29);
+        assert_eq!(model.master().isr(), 0x80);
+        unsafe { pics.notify_end_of_interrupt(0x29) };
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+        assert_eq!(pics.port_io().slave().isr(), 0x00);
+    }
+
+    #[test]
+    fn layout_keeps_earlier_modes() {
+        let mut model = ChainedModel::new();
+        let mut pics = unsafe { ChainedPics::with_port_io(0x20, 0x28, &mut model) }
+            .with_auto_eoi(true, true)
+            .with_layout(PortLayout::PC_AT)
+            .with_delay(Delay::Immediate);
+        unsafe { pics.initialize_with_mask(0x00, 0x00) };
+        assert!(pics.port_io().master().auto_eoi());
+        assert!(pics.port_io().slave().auto_eoi());
+    }
+
+    #[test]
+    fn end_of_interrupt_on_master() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        let model = pics.port_io_mut();
+        model.raise_irq(Irq::TIMER);
+        assert_eq!(model.acknowledge(), 0x20);
+        assert_eq!(model.master().isr(), 0x01);
+        unsafe { pics.notify_end_of_interrupt(0x20) };
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+    }
+
+    #[test]
+    fn end_of_interrupt_on_slave() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        let model = pics.port_io_mut();
+        model.raise_irq(Irq::RTC);
+        assert_eq!(model.acknowledge(), 0x28);
+        assert_eq!(model.master().isr(), 0x04);
+        assert_eq!(model.slave().isr(), 0x01);
+        unsafe { pics.notify_end_of_interrupt(0x28) };
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+        assert_eq!(pics.port_io().slave().isr(), 0x00);
+    }
+
+    #[test]
+    fn spurious_irq_7_is_not_acknowledged() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        let model = pics.port_io_mut();
+        model.raise_irq(Irq::TIMER);
+        assert_eq!(model.acknowledge(), 0x20);
+        model.raise_irq(Irq::LPT1);
+        model.lower_irq(Irq::LPT1);
+        assert_eq!(model.acknowledge(), 0x27);
+        unsafe {
+            assert!(pics.handle_spurious_interrupt(0x27));
+            assert!(!pics.handle_spurious_interrupt(0x20));
+        }
+        // The timer is still in service, so no EOI went to PIC1.
+        assert_eq!(pics.port_io().master().isr(), 0x01);
+    }
+
+    #[test]
+    fn spurious_irq_15_ends_the_cascade_line() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        let irq15 = Irq::new(15).unwrap();
+        let model = pics.port_io_mut();
+        model.raise_irq(irq15);
+        unsafe {
+            // PIC1 acknowledges its cascade line, and then the request
+            // goes away before PIC2 is asked for its vector.
+            model.write(0x20, 0x0C);
+            assert_eq!(model.read(0x20), 0x82);
+        }
+        model.lower_irq(irq15);
+        assert_eq!(model.master().isr(), 0x04);
+        unsafe { assert!(pics.handle_spurious_interrupt(0x2F)) };
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+        assert_eq!(pics.port_io().slave().isr(), 0x00);
+    }
+
+    #[test]
+    fn poll_reaches_the_slave() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        unsafe {
+            assert_eq!(pics.poll(), None);
+            pics.port_io_mut().raise_irq(Irq::MOUSE);
+            assert_eq!(pics.poll(), Some(Irq::MOUSE));
+            assert_eq!(pics.port_io().master().isr(), 0x04);
+            assert_eq!(pics.port_io().slave().isr(), 0x10);
+            let vector = pics.irq_to_vector(Irq::MOUSE).unwrap();
+            pics.notify_end_of_interrupt(vector);
+        }
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+        assert_eq!(pics.port_io().slave().isr(), 0x00);
+    }
+
+    #[test]
+    fn special_fully_nested_mode_lets_the_slave_nest() {
+        let mut model = ChainedModel::new();
+        let mut pics = unsafe { ChainedPics::with_port_io(0x20, 0x28, &mut model) }
+            .with_delay(Delay::Immediate)
+            .with_special_fully_nested(true);
+        unsafe { pics.initialize_with_mask(0x00, 0x00) };
+        let model = pics.port_io_mut();
+        assert!(model.master().special_fully_nested());
+        model.raise_irq(Irq::MOUSE);
+        assert_eq!(model.acknowledge(), 0x2C);
+        model.raise_irq(Irq::new(9).unwrap());
+        assert_eq!(model.acknowledge(), 0x29);
+        assert_eq!(model.slave().isr(), 0x12);
+
+        // PIC1's cascade line stays in service until PIC2 has nothing left.
+        unsafe { pics.notify_end_of_interrupt(0x29) };
+        assert_eq!(pics.port_io().slave().isr(), 0x10);
+        assert_eq!(pics.port_io().master().isr(), 0x04);
+        unsafe { pics.notify_end_of_interrupt(0x2C) };
+        assert_eq!(pics.port_io().slave().isr(), 0x00);
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+    }
+
+    #[test]
+    fn restore_state_checks_offsets() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        unsafe {
+            let mut state = pics.save_state();
+            state.offsets = [0x08, 0x70];
+            assert_eq!(
+                pics.restore_state(&state),
+                Err(VectorLayoutError::ReservedVector(0x08))
+            );
+        }
+        assert_eq!(pics.port_io().master().offset(), 0x20);
+        assert_eq!(
+            ChainedPics::validate_single_offset(0x24),
+            Err(VectorLayoutError::Misaligned(0x24))
+        );
+        assert!(
+            unsafe { ChainedPics::try_single_with_port_io(0x10, ChainedModel::new()) }.is_err()
+        );
+    }
+
+    #[test]
+    fn single_mode_leaves_pic2_alone() {
+        let mut model = ChainedModel::new();
+        let mut pics = unsafe { ChainedPics::single_with_port_io(0x20, &mut model) }
+            .with_delay(Delay::Immediate);
+        unsafe { pics.initialize_with_mask(0x00, 0x00) };
+        assert_eq!(pics.irq_to_vector(Irq::RTC), None);
+        let model = pics.port_io_mut();
+        assert!(model.master().is_initialized());
+        assert_eq!(model.master().icw1() & 0x02, 0x02);
+        assert!(!model.slave().is_initialized());
+        model.raise_irq(Irq::KEYBOARD);
+        assert_eq!(model.acknowledge(), 0x21);
+        unsafe { pics.notify_end_of_interrupt(0x21) };
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+    }
+
+    #[test]
+    fn self_test_in_single_mode() {
+        let mut model = ChainedModel::new();
+        let mut pics = unsafe { ChainedPics::single_with_port_io(0x20, &mut model) }
+            .with_delay(Delay::Immediate);
+        unsafe { pics.initialize_with_mask(0x00, 0x00) };
+        let report = unsafe { pics.self_test() };
+        assert!(report.irr_sane);
+        assert!(report.passed());
+        let text = report.to_string();
+        assert!(text.contains("PIC1: ICW1 0x13"));
+        assert!(!text.contains("PIC2"));
+    }
+
+    #[test]
+    fn disable_ends_interrupts_in_service() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        let model = pics.port_io_mut();
+        model.raise_irq(Irq::COM1);
+        assert_eq!(model.acknowledge(), 0x24);
+        model.raise_irq(Irq::RTC);
+        assert_eq!(model.acknowledge(), 0x28);
+        assert_eq!(model.master().isr(), 0x14);
+        assert_eq!(model.slave().isr(), 0x01);
+
+        // Masked lines stay in service after a non-specific EOI in special
+        // mask mode, so this is the hard case.
+        unsafe {
+            let mut guard = pics.special_mask_mode();
+            guard.disable();
+        }
+        assert_eq!(pics.port_io().master().isr(), 0x00);
+        assert_eq!(pics.port_io().slave().isr(), 0x00);
+        assert_eq!(pics.port_io().master().offset(), 0xF0);
+    }
+
+    #[test]
+    fn disable_and_restore_state() {
+        let mut model = ChainedModel::new();
+        let mut pics = initialized(&mut model);
+        unsafe {
+            pics.write_masks(0xB8, 0x8E);
+            let state = pics.save_state();
+            pics.disable();
+            assert_eq!(pics.port_io().master().offset(), 0xF0);
+            assert_eq!(pics.port_io().slave().offset(), 0xF8);
+            assert_eq!(pics.read_masks(), [0xFF, 0xFF]);
+
+            assert_eq!(pics.restore_state(&state), Ok(()));
+        }
+        assert_eq!(pics.port_io().master().offset(), 0x20);
+        assert_eq!(pics.port_io().slave().offset(), 0x28);
+        assert_eq!(pics.port_io().master().imr(), 0xB8);
+        assert_eq!(pics.port_io().slave().imr(), 0x8E);
+    }
+}
@@ -0,0 +1,117 @@
+//! Access to the processor's I/O ports.  Everything in this crate talks
+//! to the hardware through the `PortIo` trait, so that the PIC logic can
+//! be pointed at something other than real x86 ports: a recording mock in
+//! a host-side test, for example, or a different backend on an unusual
+//! platform.
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use core::arch::asm;
+
+/// A backend which can read and write 8-bit I/O ports.
+pub trait PortIo {
+    /// Read a byte from the specified I/O port.
+    unsafe fn read(&mut self, port: u16) -> u8;
+
+    /// Write a byte to the specified I/O port.
+    unsafe fn write(&mut self, port: u16, value: u8);
+}
+
+/// The default backend, which uses the x86 `in` and `out` instructions.
+/// It only implements `PortIo` when we're built for x86 or x86_64, so
+/// elsewhere, such as when running the `model` tests on another host, pass
+/// your own backend to `with_port_io` instead.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct X86PortIo;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl PortIo for X86PortIo {
+    unsafe fn read(&mut self, port: u16) -> u8 {
+        let value: u8;
+        asm!(
+            "in al, dx",
+            out("al") value,
+            in("dx") port,
+            options(nomem, nostack, preserves_flags)
+        );
+        value
+    }
+
+    unsafe fn write(&mut self, port: u16, value: u8) {
+        asm!(
+            "out dx, al",
+            in("dx") port,
+            in("al") value,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Allow a backend to be shared by borrowing it, so that a test can keep
+/// hold of its mock while the PICs are using it.
+impl<P: PortIo + ?Sized> PortIo for &mut P {
+    unsafe fn read(&mut self, port: u16) -> u8 {
+        (**self).read(port)
+    }
+
+    unsafe fn write(&mut self, port: u16, value: u8) {
+        (**self).write(port, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PortIo;
+    use ChainedPics;
+
+    /// Records every write, and answers reads from the PICs' data ports
+    /// with some distinctive masks.
+    struct Recorder {
+        writes: [(u16, u8); 32],
+        count: usize,
+    }
+
+    impl PortIo for Recorder {
+        unsafe fn read(&mut self, port: u16) -> u8 {
+            match port {
+                0x21 => 0xB8,
+                0xA1 => 0x8E,
+                _ => 0x00,
+            }
+        }
+
+        unsafe fn write(&mut self, port: u16, value: u8) {
+            self.writes[self.count] = (port, value);
+            self.count += 1;
+        }
+    }
+
+    #[test]
+    fn initialize_sends_the_pc_at_sequence() {
+        let mut recorder = Recorder {
+            writes: [(0, 0); 32],
+            count: 0,
+        };
+        unsafe { ChainedPics::with_port_io(0x20, 0x28, &mut recorder).initialize() };
+        let expected = [
+            (0x20, 0x11),
+            (0x80, 0x00),
+            (0xA0, 0x11),
+            (0x80, 0x00),
+            (0x21, 0x20),
+            (0x80, 0x00),
+            (0xA1, 0x28),
+            (0x80, 0x00),
+            (0x21, 0x04),
+            (0x80, 0x00),
+            (0xA1, 0x02),
+            (0x80, 0x00),
+            (0x21, 0x01),
+            (0x80, 0x00),
+            (0xA1, 0x01),
+            (0x80, 0x00),
+            (0x21, 0xB8),
+            (0xA1, 0x8E),
+        ];
+        assert_eq!(&recorder.writes[..recorder.count], &expected[..]);
+    }
+}